@@ -0,0 +1,57 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use libchickadee::util::scanner::{scan_parallel, Scanner};
+use libchickadee::util::{get_all_ipv4, get_all_ipv6, Ipv4Strictness};
+
+/// Firewall-style log lines mixing IPv4, IPv6, timestamps and version strings
+fn sample_log(lines: usize) -> String {
+    (0..lines)
+        .map(|i| {
+            format!(
+                "2023-04-{:02}T12:{:02}:{:02}Z action=allow src={}.{}.{}.{}:{} dst=2001:db8:{:x}::{:x} agent=Mozilla/5.0 Chrome/120.0.6099.109\n",
+                i % 28 + 1,
+                i % 60,
+                (i * 7) % 60,
+                i % 223 + 1,
+                (i * 3) % 256,
+                (i * 5) % 256,
+                (i * 11) % 256,
+                1024 + i % 60000,
+                i % 65536,
+                (i * 13) % 65536,
+            )
+        })
+        .collect()
+}
+
+fn bench_extract(c: &mut Criterion) {
+    let data = sample_log(10_000);
+    let mut group = c.benchmark_group("extract");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("regex_two_pass", |b| {
+        b.iter(|| {
+            let mut ips = get_all_ipv4(black_box(&data));
+            ips.append(&mut get_all_ipv6(black_box(&data)));
+            ips
+        })
+    });
+    group.bench_function("single_pass_scanner", |b| {
+        b.iter(|| {
+            Scanner::new(black_box(data.as_bytes()), Ipv4Strictness::Standard)
+                .map(|found| found.ip)
+                .collect::<Vec<_>>()
+        })
+    });
+    group.bench_function("parallel_scanner_64k_chunks", |b| {
+        b.iter(|| {
+            scan_parallel(
+                black_box(data.as_bytes()),
+                Ipv4Strictness::Standard,
+                64 * 1024,
+            )
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_extract);
+criterion_main!(benches);
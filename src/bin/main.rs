@@ -1,21 +1,245 @@
-use anyhow::Result;
-use clap::{self, Parser};
+use anyhow::{anyhow, Result};
+use clap::{self, Parser, ValueEnum};
+use libchickadee::parser::access_log::parse_access_log_reader;
+use libchickadee::parser::delimited::parse_delimited_reader;
+use libchickadee::parser::email::parse_email_reader;
+use libchickadee::parser::json::parse_json_reader;
+use libchickadee::parser::syslog::parse_syslog_reader;
+use libchickadee::parser::zeek::{self, parse_zeek_reader};
 use libchickadee::parser::{
-    compressed::parse_gzip_file, determine_file_type, plain::parse_text_file, SourceFileType,
+    add_seen_range, parse_file_hostnames, parse_file_with, HeaderMode, InputFormat, ParseOptions,
+    Sighting,
 };
-use libchickadee::{resolver::ip_api::Resolver, util::get_all_ips};
-use std::{net::IpAddr, path::Path};
+use libchickadee::resolver::dns::DnsResolver;
+use libchickadee::resolver::ip_api::{IpApiRecord, IpApiRecords, Resolver};
+use libchickadee::stats::Stats;
+use libchickadee::util::classify::{self, classify};
+use libchickadee::util::classify::{embedded_ipv4, Embedding};
+use libchickadee::util::hostname::get_all_hostnames;
+use libchickadee::util::prefix_set::PrefixSet;
+use libchickadee::util::special_purpose::{Category, SpecialPurposeFilter};
+use libchickadee::util::{
+    filter_allowed_ips, filter_denied_ips, filter_special_purpose_ips, get_all_endpoints_with,
+    get_distinct_ips, unwrap_embedded_ips, Ipv4Strictness, UnwrapMode,
+};
+use libchickadee::writer::columnar::{write_arrow_ipc, write_parquet};
+use libchickadee::writer::elastic::{write_bulk, write_index_template, ElasticOptions};
+use libchickadee::writer::misp::{write_misp, MispContext, MispOptions, MispRole};
+use libchickadee::writer::{html::write_html, stix::write_stix};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
 
 fn resolve_ip_addresses(
     ip_addresses: Vec<IpAddr>,
     columns: Option<Vec<String>>,
-) -> Result<Vec<String>> {
-    let ip_records = Resolver::new(columns).resolve(ip_addresses)?;
-    Ok(ip_records
+) -> Result<IpApiRecords> {
+    Resolver::new(columns).resolve(ip_addresses)
+}
+
+/// Add the local address classification to each record as extra columns
+fn classify_records(ip_records: &mut IpApiRecords) {
+    for record in ip_records.records.iter_mut() {
+        let ip = match record.query.as_deref().map(str::parse::<IpAddr>) {
+            Some(Ok(ip)) => ip,
+            _ => continue,
+        };
+        for (column, value) in classify(&ip).to_columns() {
+            record.context.insert(column.to_string(), value);
+        }
+    }
+}
+
+/// Add the PTR name of each record's address as a ptr column, looking up
+/// each distinct address once with the given number of lookups at once
+fn ptr_records(ip_records: &mut IpApiRecords, dns: &DnsResolver, concurrency: usize) -> Result<()> {
+    let ips: Vec<IpAddr> = ip_records
+        .records
+        .iter()
+        .filter_map(|record| record.query.as_deref()?.parse().ok())
+        .collect();
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(concurrency)
+        .build()?;
+    let names: HashMap<IpAddr, String> = pool.install(|| {
+        get_distinct_ips(ips)
+            .into_par_iter()
+            .filter_map(|ip| match dns.lookup_ptr(ip) {
+                Ok(name) => name.map(|name| (ip, name)),
+                Err(e) => {
+                    eprintln!("Unable to look up the PTR name of {}: {}", ip, e);
+                    None
+                }
+            })
+            .collect()
+    });
+    for record in ip_records.records.iter_mut() {
+        let ip = match record.query.as_deref().map(str::parse::<IpAddr>) {
+            Some(Ok(ip)) => ip,
+            _ => continue,
+        };
+        if let Some(name) = names.get(&ip) {
+            record.context.insert(String::from("ptr"), name.to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Unwrap embedded IPv4 addresses, keeping the context of each sighting.
+/// Each unwrapped address records the `transition` and
+/// `transition_address` it was found in. IPv4-mapped addresses are only
+/// IPv4 addresses written as IPv6, which are dropped as special-purpose, so
+/// they are unwrapped even when other transition addresses are kept.
+fn unwrap_sightings(sightings: Vec<Sighting>, mode: UnwrapMode) -> Vec<Sighting> {
+    sightings
+        .into_iter()
+        .flat_map(|sighting| {
+            let embedding = match sighting.ip {
+                IpAddr::V6(v6) => embedded_ipv4(&v6).map(|(embedding, _)| (embedding, v6)),
+                IpAddr::V4(_) => None,
+            };
+            let mode = match embedding {
+                Some((Embedding::Ipv4Mapped, _)) if mode == UnwrapMode::Keep => UnwrapMode::Replace,
+                _ => mode,
+            };
+            let context = sighting.context;
+            unwrap_embedded_ips(vec![sighting.ip], mode)
+                .into_iter()
+                .map(move |ip| {
+                    let unwrapped = Sighting {
+                        ip,
+                        context: context.clone(),
+                    };
+                    match embedding {
+                        Some((embedding, v6)) if ip.is_ipv4() => unwrapped
+                            .with("transition", embedding.to_string())
+                            .with("transition_address", v6.to_string()),
+                        _ => unwrapped,
+                    }
+                })
+        })
+        .collect()
+}
+
+/// The addresses of each hostname, tagged with the name in `resolved_from`
+/// so that it does not clash with the `hostname` of syslog lines. Names
+/// that do not resolve are skipped.
+fn resolve_hostnames(names: &[String], dns: &DnsResolver) -> Vec<Sighting> {
+    names
+        .par_iter()
+        .flat_map(|name| match dns.lookup_host(name) {
+            Ok(ips) => ips
+                .into_iter()
+                .map(|ip| Sighting::new(ip).with("resolved_from", name.as_str()))
+                .collect(),
+            Err(e) => {
+                eprintln!("Unable to resolve {}: {}", name, e);
+                vec![]
+            }
+        })
+        .collect()
+}
+
+/// Why an address is dropped before resolution, or `None` if it is kept
+fn skip_reason(ip: &IpAddr, filter: &FilterOptions) -> Option<String> {
+    if filter.special_purpose.is_excluded(ip) {
+        return classify(ip).special_purpose.map(|block| {
+            format!(
+                "special-purpose {} address ({})",
+                block.category, block.name
+            )
+        });
+    }
+    if filter.allowed.as_ref().is_some_and(|a| !a.contains(ip)) {
+        return Some(String::from("outside the allowed prefixes"));
+    }
+    if filter.denied.as_ref().is_some_and(|d| d.contains(ip)) {
+        return Some(String::from("inside a denied prefix"));
+    }
+    None
+}
+
+/// Report each distinct address that is dropped before resolution, and
+/// why, on stderr
+fn report_skipped(sightings: &[Sighting], filter: &FilterOptions) {
+    let ips = get_distinct_ips(sightings.iter().map(|s| s.ip).collect());
+    for ip in ips {
+        if let Some(reason) = skip_reason(&ip, filter) {
+            eprintln!("Skipped {}: {}", ip, reason);
+        }
+    }
+}
+
+/// Drop the sightings of addresses that should not be sent to the resolver
+fn filter_sightings(sightings: Vec<Sighting>, filter: &FilterOptions) -> Vec<Sighting> {
+    let mut ips = get_distinct_ips(sightings.iter().map(|s| s.ip).collect());
+    ips = filter_special_purpose_ips(ips, &filter.special_purpose);
+    if let Some(allowed) = &filter.allowed {
+        ips = filter_allowed_ips(ips, allowed);
+    }
+    if let Some(denied) = &filter.denied {
+        ips = filter_denied_ips(ips, denied);
+    }
+    let kept: HashSet<IpAddr> = ips.into_iter().collect();
+    sightings
+        .into_iter()
+        .filter(|s| kept.contains(&s.ip))
+        .collect()
+}
+
+/// One record per distinct address and context, so that sightings without
+/// context give one record per address, with the sighting context added
+/// to the record of its address
+fn sighting_records(sightings: &[Sighting], resolved: IpApiRecords) -> IpApiRecords {
+    let mut by_ip: HashMap<IpAddr, IpApiRecord> = HashMap::new();
+    for record in resolved.records {
+        if let Some(Ok(ip)) = record.query.as_deref().map(str::parse::<IpAddr>) {
+            by_ip.entry(ip).or_insert(record);
+        }
+    }
+    let mut seen = HashSet::new();
+    let mut ip_records = IpApiRecords::new();
+    for sighting in sightings {
+        if !seen.insert((sighting.ip, &sighting.context)) {
+            continue;
+        }
+        if let Some(record) = by_ip.get(&sighting.ip) {
+            let mut record = record.clone();
+            record.context.extend(sighting.context.clone());
+            ip_records.records.push(record);
+        }
+    }
+    ip_records
+}
+
+/// Context keys of the sightings, in order of first appearance
+fn context_columns(sightings: &[Sighting]) -> Vec<String> {
+    let mut columns: Vec<String> = vec![];
+    for sighting in sightings {
+        for key in sighting.context.keys() {
+            if !columns.contains(key) {
+                columns.push(key.to_string());
+            }
+        }
+    }
+    columns
+}
+
+/// Selected columns followed by locally computed columns
+fn with_extra_columns(columns: Option<Vec<String>>, extra: &[&str]) -> Vec<String> {
+    let mut columns = columns.unwrap_or_else(Resolver::allowed_columns);
+    columns.extend(extra.iter().map(|c| c.to_string()));
+    columns
+}
+
+fn serialize_records(ip_records: &IpApiRecords) -> Vec<String> {
+    ip_records
         .records
         .iter()
         .filter_map(|record| serde_json::to_string(record).ok())
-        .collect())
+        .collect()
 }
 
 fn print_records(ip_records: Vec<String>, columns: Option<Vec<String>>) {
@@ -59,87 +283,623 @@ fn print_records(ip_records: Vec<String>, columns: Option<Vec<String>>) {
     }
 }
 
+fn write_records(
+    ip_records: &IpApiRecords,
+    columns: Option<Vec<String>>,
+    sources: &[String],
+    output: &OutputOptions,
+) -> Result<()> {
+    match output.format {
+        OutputFormat::Json => {
+            print_records(serialize_records(ip_records), columns);
+            Ok(())
+        }
+        OutputFormat::Html => write_html(
+            ip_records,
+            columns.as_deref(),
+            sources,
+            &mut std::io::stdout().lock(),
+        ),
+        OutputFormat::Stix => write_stix(ip_records, &mut std::io::stdout().lock()),
+        OutputFormat::Misp => write_misp(ip_records, &output.misp, &mut std::io::stdout().lock()),
+        OutputFormat::Elastic => {
+            if let Some(template_path) = &output.elastic_template {
+                let mut template_file = std::fs::File::create(template_path)?;
+                write_index_template(&output.elastic, &mut template_file)?;
+            }
+            write_bulk(ip_records, &output.elastic, &mut std::io::stdout().lock())
+        }
+        OutputFormat::Parquet => write_parquet(ip_records, columns.as_deref(), std::io::stdout()),
+        OutputFormat::Arrow => write_arrow_ipc(ip_records, columns.as_deref(), std::io::stdout()),
+    }
+}
+
 struct Extractor {
     // Extract IP address from input
     source: String,
     is_file: bool,
+    options: ParseOptions,
 }
 
 impl Extractor {
-    fn new(source: String) -> Self {
+    fn new(source: String, options: &ParseOptions) -> Self {
         let is_file = Path::new(&source).exists();
-        Self { source, is_file }
+        Self {
+            source,
+            is_file,
+            options: options.clone(),
+        }
     }
 
-    fn extract(&self) -> Result<Vec<IpAddr>> {
-        if !self.is_file {
-            // This must be a string input
-            return Ok(get_all_ips(self.source.as_str()));
+    fn sources(&self) -> Vec<String> {
+        if self.is_file {
+            vec![self.source.clone()]
+        } else {
+            vec![]
         }
+    }
+
+    fn extract(&self) -> Result<Vec<Sighting>> {
+        let mut sightings = self.extract_addresses()?;
+        if let Some(dns) = &self.options.hostnames {
+            let names = if self.is_file {
+                parse_file_hostnames(Path::new(&self.source))?
+            } else {
+                get_all_hostnames(&self.source)
+            };
+            sightings.extend(resolve_hostnames(&names, dns));
+        }
+        Ok(sightings)
+    }
 
-        let source_path = Path::new(&self.source);
+    fn extract_addresses(&self) -> Result<Vec<Sighting>> {
+        if self.is_file {
+            return parse_file_with(Path::new(&self.source), &self.options);
+        }
 
-        match determine_file_type(source_path)? {
-            SourceFileType::Plain => parse_text_file(source_path),
-            SourceFileType::Gzip => parse_gzip_file(source_path),
-            SourceFileType::NotAFile => Ok(get_all_ips(self.source.as_str())),
+        // This must be a string input
+        match self.options.format {
+            InputFormat::Text if self.source.as_bytes().starts_with(zeek::HEADER) => {
+                parse_zeek_reader(self.source.as_bytes(), &self.options.fields)
+            }
+            InputFormat::Text => Ok(self.options.text_sightings(get_all_endpoints_with(
+                self.source.as_bytes(),
+                self.options.strictness,
+            ))),
+            InputFormat::Json => parse_json_reader(self.source.as_bytes(), &self.options.fields),
+            InputFormat::Delimited => parse_delimited_reader(
+                self.source.as_bytes(),
+                self.options.delimiter.unwrap_or(b','),
+                self.options.header,
+                &self.options.fields,
+            ),
+            InputFormat::AccessLog => {
+                parse_access_log_reader(self.source.as_bytes(), &self.options.access_log_format()?)
+            }
+            InputFormat::Syslog => {
+                parse_syslog_reader(self.source.as_bytes(), self.options.strictness)
+            }
+            InputFormat::Zeek => parse_zeek_reader(self.source.as_bytes(), &self.options.fields),
+            InputFormat::Email => parse_email_reader(self.source.as_bytes()),
+            InputFormat::Capture | InputFormat::Evtx => {
+                Err(anyhow!("Binary formats must be read from a file"))
+            }
         }
     }
 }
 
+#[derive(Clone, Copy, Default, ValueEnum)]
+enum OutputFormat {
+    /// One JSON object per line
+    #[default]
+    Json,
+    /// A standalone HTML report
+    Html,
+    /// A STIX 2.1 bundle
+    Stix,
+    /// A MISP event
+    Misp,
+    /// Elasticsearch/OpenSearch bulk NDJSON with ECS field names
+    Elastic,
+    /// A Parquet file with a typed column per selected field
+    Parquet,
+    /// An Arrow IPC file with a typed column per selected field
+    Arrow,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum InputFormatArg {
+    Text,
+    Json,
+    Csv,
+    Tsv,
+    /// Apache or Nginx access logs
+    AccessLog,
+    /// RFC 3164 or RFC 5424 syslog, such as auth.log
+    Syslog,
+    /// Zeek TSV or JSON logs. TSV logs are also detected as text.
+    Zeek,
+    /// The Received headers of .eml or mbox files
+    Email,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum HeaderArg {
+    Detect,
+    Present,
+    Absent,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum StatsMode {
+    /// Print the summary instead of the records
+    Only,
+    /// Write the records, then print the summary to stderr
+    Alongside,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Ipv4StrictnessArg {
+    /// Accept any match, even inside longer runs of digits and dots
+    Lenient,
+    /// Reject matches inside longer runs of digits and dots, such as
+    /// 1.1.1.1234
+    Standard,
+    /// Also reject matches touching letters, '_' or '-', and version
+    /// strings such as Chrome/120.0.0.1
+    Strict,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum UnwrapModeArg {
+    Keep,
+    Replace,
+    Both,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum MispRoleArg {
+    Src,
+    Dst,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum MispContextArg {
+    Comment,
+    Object,
+}
+
+/// Settings for unwrapping and dropping addresses before resolution
+#[derive(Default)]
+struct FilterOptions {
+    unwrap: UnwrapMode,
+    special_purpose: SpecialPurposeFilter,
+    allowed: Option<PrefixSet>,
+    denied: Option<PrefixSet>,
+}
+
+/// Settings for writing the resolved records
+#[derive(Default)]
+struct OutputOptions {
+    format: OutputFormat,
+    misp: MispOptions,
+    elastic: ElasticOptions,
+    elastic_template: Option<String>,
+    classify: bool,
+    /// Resolver for the PTR name of each distinct address
+    ptr: Option<DnsResolver>,
+    ptr_concurrency: usize,
+    ptr_cache: Option<String>,
+    stats: Option<StatsMode>,
+    stats_top: usize,
+}
+
 // Create new struct for Clap to parse CLI arguments
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 struct Cli {
     /// IP addresses to resolve from CLI arguments using clap.
     /// This may be a delimited string or a file path to a text file
-    /// containing one or more IP addresses.
+    /// containing one or more IP addresses. Repeat to read several
+    /// sources, which are extracted in parallel.
+    #[clap(long, required = true)]
+    ips: Vec<String>,
+
+    /// How to read the sources: scan all text, or read JSON lines,
+    /// delimited rows, access logs, syslog, Zeek logs or email headers.
+    #[clap(long, value_enum, default_value = "text")]
+    input_format: InputFormatArg,
+
+    /// Fields to read addresses from in structured input, such as
+    /// sourceIPAddress,client.ipAddress for JSON or src,3 for CSV columns
+    /// named by header or numbered from 1. All fields are read by default,
+    /// except for Zeek logs, which default to id.orig_h,id.resp_h.
+    #[clap(long, value_delimiter = ',')]
+    fields: Vec<String>,
+
+    /// Delimiter of CSV input, such as ';' or '|'. Defaults to a tab for
+    /// .tsv files and a comma otherwise.
     #[clap(long)]
-    ips: String,
+    delimiter: Option<char>,
+
+    /// Whether CSV and TSV input starts with a header row. By default, the
+    /// first row is a header if it holds no addresses.
+    #[clap(long, value_enum, default_value = "detect")]
+    header: HeaderArg,
+
+    /// Format of access log lines: common, combined, or a custom Apache
+    /// LogFormat or Nginx log_format string.
+    #[clap(long, default_value = "combined")]
+    log_format: String,
+
+    /// Record the port and IPv6 zone written with each address of text
+    /// input, such as 1.2.3.4:8080, [2001:db8::1]:443 or fe80::1%eth0, in
+    /// port and zone columns.
+    #[clap(long)]
+    ports: bool,
+
+    /// Resolve the hostnames and URLs in the input, including defanged
+    /// names such as evil[.]com, and record each address with the hostname
+    /// it was resolved from in a resolved_from column.
+    #[clap(long)]
+    hostnames: bool,
+
+    /// DNS server used to resolve hostnames and PTR names, such as 9.9.9.9
+    /// or 127.0.0.1:5353. The system resolver is used by default.
+    #[clap(long)]
+    dns_server: Option<String>,
+
+    /// Seconds to wait for each answer from the DNS server or the system
+    /// resolver.
+    #[clap(long, default_value_t = 5)]
+    dns_timeout: u64,
+
+    /// Number of threads used to extract addresses, or 0 for one per core.
+    #[clap(long, default_value_t = 0)]
+    threads: usize,
 
     /// Specify which columns to select in the GeoIP resolution.
     /// Currently only supports the columns for ip-api.com.
     #[clap(long)]
     columns: Option<String>,
+
+    /// How strictly IPv4 addresses in text input and syslog messages must
+    /// be separated from the surrounding text.
+    #[clap(long, value_enum, default_value = "standard")]
+    ipv4_strictness: Ipv4StrictnessArg,
+
+    /// Whether to resolve the IPv4 address embedded in IPv4-mapped, 6to4,
+    /// Teredo and NAT64 addresses instead of, or as well as, the IPv6
+    /// address. IPv4-mapped addresses are resolved as their IPv4 address
+    /// unless both are asked for.
+    #[clap(long, value_enum, default_value = "keep")]
+    unwrap_embedded: UnwrapModeArg,
+
+    /// Special-purpose address categories to resolve instead of dropping,
+    /// such as private,shared. Available categories: unspecified, loopback,
+    /// private, shared, link-local, unique-local, documentation,
+    /// benchmarking, protocol, translation, discard, reserved, broadcast,
+    /// multicast.
+    #[clap(long, value_delimiter = ',')]
+    allow_special: Vec<Category>,
+
+    /// Only resolve addresses within the prefixes listed in these files,
+    /// one prefix per line with `#` comments.
+    #[clap(long)]
+    allow_cidrs: Vec<String>,
+
+    /// Never resolve addresses within the prefixes listed in these files,
+    /// one prefix per line with `#` comments.
+    #[clap(long)]
+    deny_cidrs: Vec<String>,
+
+    /// Format to write the resolved records in.
+    #[clap(long, value_enum, default_value = "json")]
+    format: OutputFormat,
+
+    /// Attribute type of the addresses in a MISP event.
+    #[clap(long, value_enum, default_value = "src")]
+    misp_role: MispRoleArg,
+
+    /// Record geolocation and ASN details in a MISP event as attribute
+    /// comments or as objects.
+    #[clap(long, value_enum, default_value = "comment")]
+    misp_context: MispContextArg,
+
+    /// Index named in the action lines of Elasticsearch bulk output.
+    #[clap(long, default_value = "chickadee")]
+    es_index: String,
+
+    /// ECS field set the enrichment is written under in Elasticsearch bulk
+    /// output, such as source or destination.
+    #[clap(long, default_value = "source")]
+    es_field_set: String,
+
+    /// Write an index template with the mappings of the Elasticsearch bulk
+    /// output to this path. Requires --format elastic.
+    #[clap(long)]
+    es_template: Option<String>,
+
+    /// Add columns describing each address without a lookup: family,
+    /// scope, category, special_purpose, embedding, embedded_ipv4 and
+    /// reverse_name. Addresses dropped before resolution are listed on
+    /// stderr with the reason they were skipped.
+    #[clap(long)]
+    classify: bool,
+
+    /// Add the PTR name of each address, such as scanner.shodan.io, in a
+    /// ptr column.
+    #[clap(long)]
+    ptr: bool,
+
+    /// Number of PTR lookups made at once.
+    #[clap(long, default_value_t = 16)]
+    ptr_concurrency: usize,
+
+    /// JSON file caching PTR names between runs. Cached addresses are not
+    /// looked up again, and new names are added to the file.
+    #[clap(long)]
+    ptr_cache: Option<String>,
+
+    /// Summarise occurrences per address, country, ASN, organisation and
+    /// flag, either instead of or alongside the records.
+    #[clap(long, value_enum)]
+    stats: Option<StatsMode>,
+
+    /// Number of most frequent values listed per summary table, or 0 for
+    /// all of them.
+    #[clap(long, default_value_t = 10)]
+    stats_top: usize,
+}
+
+fn load_filter_options(cli: &Cli) -> Result<FilterOptions> {
+    let special_purpose = cli
+        .allow_special
+        .iter()
+        .fold(SpecialPurposeFilter::default(), |filter, category| {
+            filter.allow(*category)
+        });
+    let allowed = if cli.allow_cidrs.is_empty() {
+        None
+    } else {
+        Some(PrefixSet::from_files(&cli.allow_cidrs)?)
+    };
+    let denied = if cli.deny_cidrs.is_empty() {
+        None
+    } else {
+        Some(PrefixSet::from_files(&cli.deny_cidrs)?)
+    };
+    Ok(FilterOptions {
+        unwrap: match cli.unwrap_embedded {
+            UnwrapModeArg::Keep => UnwrapMode::Keep,
+            UnwrapModeArg::Replace => UnwrapMode::Replace,
+            UnwrapModeArg::Both => UnwrapMode::Both,
+        },
+        special_purpose,
+        allowed,
+        denied,
+    })
+}
+
+/// A DNS server address, using port 53 unless one is given
+fn parse_dns_server(server: &str) -> Result<SocketAddr> {
+    server
+        .parse::<SocketAddr>()
+        .or_else(|_| server.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+        .map_err(|_| anyhow!("Invalid DNS server: {}", server))
+}
+
+fn load_dns_resolver(cli: &Cli) -> Result<DnsResolver> {
+    Ok(DnsResolver::new(
+        cli.dns_server
+            .as_deref()
+            .map(parse_dns_server)
+            .transpose()?,
+        Duration::from_secs(cli.dns_timeout),
+    ))
+}
+
+fn load_parse_options(cli: &Cli) -> Result<ParseOptions> {
+    let delimiter = match cli.delimiter {
+        Some(delimiter) if delimiter.is_ascii() => Some(delimiter as u8),
+        Some(delimiter) => return Err(anyhow!("Delimiter must be ASCII: {}", delimiter)),
+        None => None,
+    };
+    let (format, delimiter) = match cli.input_format {
+        InputFormatArg::Text => (InputFormat::Text, None),
+        InputFormatArg::Json => (InputFormat::Json, None),
+        InputFormatArg::Csv => (InputFormat::Delimited, delimiter),
+        InputFormatArg::Tsv => (InputFormat::Delimited, delimiter.or(Some(b'\t'))),
+        InputFormatArg::AccessLog => (InputFormat::AccessLog, None),
+        InputFormatArg::Syslog => (InputFormat::Syslog, None),
+        InputFormatArg::Zeek => (InputFormat::Zeek, None),
+        InputFormatArg::Email => (InputFormat::Email, None),
+    };
+    let options = ParseOptions {
+        format,
+        fields: cli.fields.clone(),
+        delimiter,
+        header: match cli.header {
+            HeaderArg::Detect => HeaderMode::Detect,
+            HeaderArg::Present => HeaderMode::Present,
+            HeaderArg::Absent => HeaderMode::Absent,
+        },
+        log_format: Some(cli.log_format.clone()),
+        ports: cli.ports,
+        strictness: match cli.ipv4_strictness {
+            Ipv4StrictnessArg::Lenient => Ipv4Strictness::Lenient,
+            Ipv4StrictnessArg::Standard => Ipv4Strictness::Standard,
+            Ipv4StrictnessArg::Strict => Ipv4Strictness::Strict,
+        },
+        hostnames: match cli.hostnames {
+            true => Some(load_dns_resolver(cli)?),
+            false => None,
+        },
+    };
+    if options.format == InputFormat::AccessLog {
+        options.access_log_format()?;
+    }
+    Ok(options)
 }
 
 fn main() {
     // Parse CLI arguments
     let cli = Cli::parse();
+
+    if cli.es_template.is_some() && !matches!(cli.format, OutputFormat::Elastic) {
+        eprintln!("Exiting due to error: --es-template requires --format elastic");
+        return;
+    }
+    if let Err(e) = rayon::ThreadPoolBuilder::new()
+        .num_threads(cli.threads)
+        .build_global()
+    {
+        eprintln!("Exiting due to error: {}", e);
+        return;
+    }
+    let input = match load_parse_options(&cli) {
+        Ok(input) => input,
+        Err(e) => {
+            eprintln!("Exiting due to error: {}", e);
+            return;
+        }
+    };
+    let filter = match load_filter_options(&cli) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("Exiting due to error: {}", e);
+            return;
+        }
+    };
+    let ptr = match cli.ptr.then(|| load_dns_resolver(&cli)).transpose() {
+        Ok(ptr) => ptr,
+        Err(e) => {
+            eprintln!("Exiting due to error: {}", e);
+            return;
+        }
+    };
     let columns = cli
         .columns
         .map(|s| s.split(',').map(|s| s.to_string()).collect());
+    let output = OutputOptions {
+        format: cli.format,
+        misp: MispOptions {
+            role: match cli.misp_role {
+                MispRoleArg::Src => MispRole::Source,
+                MispRoleArg::Dst => MispRole::Destination,
+            },
+            context: match cli.misp_context {
+                MispContextArg::Comment => MispContext::Comment,
+                MispContextArg::Object => MispContext::Object,
+            },
+            ..Default::default()
+        },
+        elastic: ElasticOptions {
+            index: cli.es_index,
+            field_set: cli.es_field_set,
+        },
+        elastic_template: cli.es_template,
+        classify: cli.classify,
+        ptr,
+        ptr_concurrency: cli.ptr_concurrency,
+        ptr_cache: cli.ptr_cache,
+        stats: cli.stats,
+        stats_top: cli.stats_top,
+    };
 
-    match run_chickadee(cli.ips, columns) {
+    match run_chickadee(cli.ips, &input, columns, &filter, &output) {
         Ok(_) => (),
         Err(e) => eprintln!("Exiting due to error: {}", e),
     };
 }
 
-fn run_chickadee(ips: String, columns: Option<Vec<String>>) -> Result<()> {
-    // Extract IP addresses
-    let extractor = Extractor::new(ips.clone());
-    let ip_addresses = match extractor.extract() {
-        Ok(ip_addresses) => Ok(ip_addresses),
-        Err(e) => {
-            eprintln!("Error while extracting IP addresses from {}: {}", ips, e);
-            Err(e)
-        }
-    }?;
+fn run_chickadee(
+    ips: Vec<String>,
+    input: &ParseOptions,
+    columns: Option<Vec<String>>,
+    filter: &FilterOptions,
+    output: &OutputOptions,
+) -> Result<()> {
+    // Extract IP addresses from each source in parallel
+    let extractors: Vec<Extractor> = ips
+        .into_iter()
+        .map(|source| Extractor::new(source, input))
+        .collect();
+    let sightings = extractors
+        .par_iter()
+        .map(|extractor| {
+            extractor.extract().map_err(|e| {
+                eprintln!(
+                    "Error while extracting IP addresses from {}: {}",
+                    extractor.source, e
+                );
+                e
+            })
+        })
+        .collect::<Result<Vec<Vec<Sighting>>>>()?;
+    let sightings: Vec<Sighting> = sightings.into_iter().flatten().collect();
+    let sources: Vec<String> = extractors.iter().flat_map(|e| e.sources()).collect();
+
+    // Unwrap IPv4 addresses from transition addresses
+    let sightings = unwrap_sightings(sightings, filter.unwrap);
+
+    // Drop addresses that should not be sent to the resolver
+    if output.classify {
+        report_skipped(&sightings, filter);
+    }
+    let mut sightings = filter_sightings(sightings, filter);
+    add_seen_range(&mut sightings);
 
-    // Resolve IP addresses
-    let ip_records = match resolve_ip_addresses(ip_addresses, columns.clone()) {
+    let mut columns = columns;
+    let context = context_columns(&sightings);
+    if !context.is_empty() {
+        let context: Vec<&str> = context.iter().map(String::as_str).collect();
+        columns = Some(with_extra_columns(columns, &context));
+    }
+    if output.classify {
+        columns = Some(with_extra_columns(columns, classify::COLUMNS));
+    }
+    if output.ptr.is_some() {
+        columns = Some(with_extra_columns(columns, &["ptr"]));
+    }
+
+    // Resolve each distinct IP address once
+    let ip_addresses: Vec<IpAddr> = sightings.iter().map(|s| s.ip).collect();
+    let resolved = match resolve_ip_addresses(get_distinct_ips(ip_addresses), columns.clone()) {
         Ok(ip_records) => Ok(ip_records),
         Err(e) => {
             eprintln!("Error during resolution: {}", e);
             Err(e)
         }
     }?;
+    let mut ip_records = sighting_records(&sightings, resolved);
+    if output.classify {
+        classify_records(&mut ip_records);
+    }
+    if let Some(dns) = &output.ptr {
+        let cache = output.ptr_cache.as_deref().map(Path::new);
+        if let Some(cache) = cache {
+            dns.load_ptr_cache(cache)?;
+        }
+        ptr_records(&mut ip_records, dns, output.ptr_concurrency)?;
+        if let Some(cache) = cache {
+            dns.save_ptr_cache(cache)?;
+        }
+    }
 
-    // Print IP records
-    print_records(ip_records, columns);
-
-    Ok(())
+    // Write IP records
+    let top = (output.stats_top > 0).then_some(output.stats_top);
+    let occurrences: Vec<IpAddr> = sightings.iter().map(|s| s.ip).collect();
+    match output.stats {
+        Some(StatsMode::Only) => {
+            Stats::new(&occurrences, &ip_records, top).write_text(&mut std::io::stdout().lock())
+        }
+        Some(StatsMode::Alongside) => {
+            write_records(&ip_records, columns, &sources, output)?;
+            Stats::new(&occurrences, &ip_records, top).write_text(&mut std::io::stderr().lock())
+        }
+        None => write_records(&ip_records, columns, &sources, output),
+    }
 }
 
 #[cfg(test)]
@@ -147,13 +907,15 @@ mod tests {
     use tempfile::NamedTempFile;
 
     use super::*;
-    use std::{io::Write, net::Ipv4Addr};
+    use std::io::Write;
+    use std::net::Ipv4Addr;
 
     #[test]
     fn test_resolve_ip_addresses() {
         let ip_addresses = vec![IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1))];
         let columns = Some(vec!["countryCode".to_string(), "query".to_string()]);
-        let ip_records = resolve_ip_addresses(ip_addresses, columns);
+        let ip_records =
+            resolve_ip_addresses(ip_addresses, columns).map(|records| serialize_records(&records));
 
         assert!(ip_records.is_ok());
         assert_eq!(1, ip_records.as_ref().unwrap().len());
@@ -173,18 +935,305 @@ mod tests {
             .unwrap();
 
         // Pass this file into
-        let extractor = Extractor::new(temp_path.path().to_string_lossy().to_string());
+        let extractor = Extractor::new(
+            temp_path.path().to_string_lossy().to_string(),
+            &ParseOptions::default(),
+        );
         let ip_addresses = extractor.extract();
 
         assert!(ip_addresses.is_ok());
         assert!(ip_addresses.unwrap().len() == 3);
     }
 
+    #[test]
+    fn test_classify_records() {
+        let mut ip_records = IpApiRecords::new();
+        for query in ["10.0.0.1", "2002:0808:0808::1"] {
+            ip_records.records.push(IpApiRecord {
+                query: Some(query.to_string()),
+                ..Default::default()
+            });
+        }
+        classify_records(&mut ip_records);
+
+        let private = &ip_records.records[0].context;
+        assert_eq!("private", private["scope"]);
+        assert_eq!("1.0.0.10.in-addr.arpa", private["reverse_name"]);
+        let six_to_four = &ip_records.records[1].context;
+        assert_eq!("ipv6", six_to_four["family"]);
+        assert_eq!("8.8.8.8", six_to_four["embedded_ipv4"]);
+
+        let columns = with_extra_columns(Some(vec!["query".to_string()]), classify::COLUMNS);
+        assert_eq!(vec!["query", "family", "scope"], columns[..3].to_vec());
+    }
+
+    #[test]
+    fn test_unwrap_sightings_transitions() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let sightings = vec![
+            Sighting::new(ip("1.2.3.4")),
+            Sighting::new(ip("::ffff:1.2.3.4")).with("field", "client"),
+        ];
+        let sightings = unwrap_sightings(sightings, UnwrapMode::Both);
+        assert_eq!(3, sightings.len());
+        // The native occurrence is not tagged as unwrapped
+        assert!(sightings[0].context.is_empty());
+        assert!(!sightings[1].context.contains_key("transition"));
+        let unwrapped = &sightings[2];
+        assert_eq!(ip("1.2.3.4"), unwrapped.ip);
+        assert_eq!("ipv4-mapped", unwrapped.context["transition"]);
+        assert_eq!("::ffff:1.2.3.4", unwrapped.context["transition_address"]);
+        assert_eq!("client", unwrapped.context["field"]);
+    }
+
+    #[test]
+    fn test_unwrap_sightings_mapped() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let sightings = vec![
+            Sighting::new(ip("::ffff:8.8.8.8")),
+            Sighting::new(ip("2002:0808:0404::1")),
+        ];
+        let sightings = filter_sightings(
+            unwrap_sightings(sightings, UnwrapMode::Keep),
+            &FilterOptions::default(),
+        );
+        let ips: Vec<IpAddr> = sightings.iter().map(|s| s.ip).collect();
+        assert_eq!(vec![ip("8.8.8.8"), ip("2002:0808:0404::1")], ips);
+        assert_eq!("ipv4-mapped", sightings[0].context["transition"]);
+        assert!(sightings[1].context.is_empty());
+    }
+
+    #[test]
+    fn test_skip_reason() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let filter = FilterOptions {
+            denied: Some(PrefixSet::from_iter(["45.33.0.0/16".parse().unwrap()])),
+            ..Default::default()
+        };
+        assert_eq!(
+            Some(String::from(
+                "special-purpose private address (Private-Use)"
+            )),
+            skip_reason(&ip("10.0.0.1"), &filter)
+        );
+        assert_eq!(
+            Some(String::from("inside a denied prefix")),
+            skip_reason(&ip("45.33.32.156"), &filter)
+        );
+        assert_eq!(None, skip_reason(&ip("8.8.8.8"), &filter));
+    }
+
+    #[test]
+    fn test_sighting_records() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let sightings = vec![
+            Sighting::new(ip("8.8.8.8")).with("field", "sourceIPAddress"),
+            Sighting::new(ip("10.0.0.1")).with("field", "sourceIPAddress"),
+            Sighting::new(ip("::ffff:9.9.9.9")).with("field", "client.ip"),
+            Sighting::new(ip("8.8.8.8")).with("field", "client.ip"),
+        ];
+        let sightings = filter_sightings(
+            unwrap_sightings(sightings, UnwrapMode::Replace),
+            &FilterOptions::default(),
+        );
+        assert_eq!(3, sightings.len());
+        assert_eq!(
+            vec!["field", "transition", "transition_address"],
+            context_columns(&sightings)
+        );
+
+        let mut resolved = IpApiRecords::new();
+        for query in ["8.8.8.8", "9.9.9.9"] {
+            resolved.records.push(IpApiRecord {
+                query: Some(query.to_string()),
+                ..Default::default()
+            });
+        }
+        let ip_records = sighting_records(&sightings, resolved);
+        let found: Vec<(&str, &str)> = ip_records
+            .records
+            .iter()
+            .map(|r| (r.query.as_deref().unwrap(), r.context["field"].as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("8.8.8.8", "sourceIPAddress"),
+                ("9.9.9.9", "client.ip"),
+                ("8.8.8.8", "client.ip"),
+            ],
+            found
+        );
+    }
+
+    #[test]
+    fn test_sighting_records_distinct() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let sightings = vec![
+            Sighting::new(ip("8.8.8.8")),
+            Sighting::new(ip("1.1.1.1")),
+            Sighting::new(ip("8.8.8.8")),
+            Sighting::new(ip("8.8.8.8")).with("port", "53"),
+        ];
+        let mut resolved = IpApiRecords::new();
+        for query in ["8.8.8.8", "1.1.1.1"] {
+            resolved.records.push(IpApiRecord {
+                query: Some(query.to_string()),
+                ..Default::default()
+            });
+        }
+        let ip_records = sighting_records(&sightings, resolved);
+        let found: Vec<&str> = ip_records
+            .records
+            .iter()
+            .map(|r| r.query.as_deref().unwrap())
+            .collect();
+        assert_eq!(vec!["8.8.8.8", "1.1.1.1", "8.8.8.8"], found);
+    }
+
+    #[test]
+    fn test_extract_json_string() {
+        let options = ParseOptions {
+            format: InputFormat::Json,
+            fields: vec![String::from("ip")],
+            ..Default::default()
+        };
+        let extractor = Extractor::new(
+            String::from(r#"{"ip": "1.1.1.1", "x": "2.2.2.2"}"#),
+            &options,
+        );
+        let sightings = extractor.extract().unwrap();
+        assert_eq!(1, sightings.len());
+        assert_eq!("ip", sightings[0].context["field"]);
+    }
+
+    #[test]
+    fn test_extract_access_log_string() {
+        let options = ParseOptions {
+            format: InputFormat::AccessLog,
+            log_format: Some(String::from("common")),
+            ..Default::default()
+        };
+        let extractor = Extractor::new(
+            String::from(
+                r#"1.1.1.1 - - [01/Apr/2023:10:00:00 +0000] "GET /2.2.2.2 HTTP/1.1" 404 0"#,
+            ),
+            &options,
+        );
+        let sightings = extractor.extract().unwrap();
+        assert_eq!(1, sightings.len());
+        assert_eq!("/2.2.2.2", sightings[0].context["path"]);
+    }
+
+    #[test]
+    fn test_extract_ports_string() {
+        let options = ParseOptions {
+            ports: true,
+            ..Default::default()
+        };
+        let extractor =
+            Extractor::new(String::from("10.0.0.5:4444 -> [2001:db8::1]:443"), &options);
+        let sightings = extractor.extract().unwrap();
+        assert_eq!("4444", sightings[0].context["port"]);
+        assert_eq!("443", sightings[1].context["port"]);
+    }
+
+    #[test]
+    fn test_ptr_records() {
+        let cache = NamedTempFile::new().unwrap();
+        std::fs::write(
+            cache.path(),
+            r#"{"192.0.2.1": "scanner.example.com", "192.0.2.2": null}"#,
+        )
+        .unwrap();
+        // Only cached names are used, as nothing answers on the server
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let dns = DnsResolver::new(
+            Some(silent.local_addr().unwrap()),
+            Duration::from_millis(50),
+        );
+        dns.load_ptr_cache(cache.path()).unwrap();
+
+        let mut ip_records = IpApiRecords::new();
+        for query in ["192.0.2.1", "192.0.2.2", "192.0.2.1"] {
+            ip_records.records.push(IpApiRecord {
+                query: Some(String::from(query)),
+                ..Default::default()
+            });
+        }
+        ptr_records(&mut ip_records, &dns, 2).unwrap();
+        let ptr: Vec<Option<&String>> = ip_records
+            .records
+            .iter()
+            .map(|record| record.context.get("ptr"))
+            .collect();
+        let name = String::from("scanner.example.com");
+        assert_eq!(vec![Some(&name), None, Some(&name)], ptr);
+    }
+
+    #[test]
+    fn test_parse_dns_server() {
+        assert_eq!(
+            "9.9.9.9:53",
+            parse_dns_server("9.9.9.9").unwrap().to_string()
+        );
+        assert_eq!(
+            "[::1]:5353",
+            parse_dns_server("[::1]:5353").unwrap().to_string()
+        );
+        assert!(parse_dns_server("dns.example").is_err());
+    }
+
+    #[test]
+    fn test_load_parse_options_delimiter() {
+        let delimiter = |args: &[&str]| {
+            let cli = Cli::parse_from([&["chickadee"], args].concat());
+            load_parse_options(&cli).unwrap().delimiter
+        };
+        assert_eq!(
+            None,
+            delimiter(&["--input-format", "csv", "--ips", "x.tsv"])
+        );
+        assert_eq!(
+            Some(b'\t'),
+            delimiter(&["--input-format", "tsv", "--ips", "x.txt"])
+        );
+        assert_eq!(
+            Some(b';'),
+            delimiter(&[
+                "--input-format",
+                "csv",
+                "--delimiter",
+                ";",
+                "--ips",
+                "x.tsv"
+            ])
+        );
+    }
+
+    #[test]
+    fn test_extract_strictness_string() {
+        let source = String::from("Chrome/120.0.0.1 from 8.8.8.8");
+        let standard = Extractor::new(source.clone(), &ParseOptions::default());
+        assert_eq!(2, standard.extract().unwrap().len());
+        let options = ParseOptions {
+            strictness: Ipv4Strictness::Strict,
+            ..Default::default()
+        };
+        let strict = Extractor::new(source, &options);
+        assert_eq!(1, strict.extract().unwrap().len());
+    }
+
     #[test]
     fn test_run_chickadee_str() {
-        let ips = "1.1.1.1,2.2.2.2\t3.3.3.3\n4.4.4.4".to_string();
+        let ips = vec!["1.1.1.1,2.2.2.2\t3.3.3.3\n4.4.4.4".to_string()];
         let columns = Some(vec!["countryCode".to_string(), "query".to_string()]);
-        let res = run_chickadee(ips, columns);
+        let res = run_chickadee(
+            ips,
+            &ParseOptions::default(),
+            columns,
+            &FilterOptions::default(),
+            &OutputOptions::default(),
+        );
         assert!(res.is_ok());
     }
 
@@ -199,9 +1248,15 @@ mod tests {
             ))
             .unwrap();
 
-        let ips = temp_path.path().to_string_lossy().to_string();
+        let ips = vec![temp_path.path().to_string_lossy().to_string()];
         let columns = Some(vec!["countryCode".to_string(), "query".to_string()]);
-        let res = run_chickadee(ips, columns);
+        let res = run_chickadee(
+            ips,
+            &ParseOptions::default(),
+            columns,
+            &FilterOptions::default(),
+            &OutputOptions::default(),
+        );
         assert!(res.is_ok());
     }
 }
@@ -1,4 +1,5 @@
 pub mod parser;
 pub mod resolver;
+pub mod stats;
 pub mod util;
 pub mod writer;
@@ -0,0 +1,290 @@
+use crate::parser::{open_reader, utc_timestamp, Sighting};
+use anyhow::{anyhow, Result};
+use chrono::DateTime;
+use regex::Regex;
+use std::io::{BufRead, BufReader, Read};
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The Common Log Format shared by Apache and Nginx
+pub const COMMON: &str = r#"%h %l %u %t "%r" %>s %b"#;
+/// The Combined Log Format, adding the referrer and user agent
+pub const COMBINED: &str = r#"%h %l %u %t "%r" %>s %b "%{Referer}i" "%{User-agent}i""#;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Field {
+    Client,
+    /// A `[10/Oct/2000:13:55:36 -0700]` timestamp
+    ClfTime,
+    /// An RFC 3339 timestamp
+    IsoTime,
+    Request,
+    Status,
+    ForwardedFor,
+    Other,
+}
+
+/// A compiled access log format.
+///
+/// Formats use Apache `LogFormat` directives, such as `%h` and
+/// `%{X-Forwarded-For}i`, or Nginx `log_format` variables, such as
+/// `$remote_addr` and `$http_x_forwarded_for`. `common` and `combined`
+/// name the standard formats.
+#[derive(Debug)]
+pub struct LogFormat {
+    pattern: Regex,
+    fields: Vec<Field>,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let format = match s {
+            "common" => COMMON,
+            "combined" => COMBINED,
+            _ => s,
+        };
+        let mut pattern = String::from("^");
+        let mut fields = vec![];
+        let mut chars = format.chars().peekable();
+        while let Some(c) = chars.next() {
+            let field = match c {
+                '%' => apache_directive(&mut chars)?,
+                '$' if chars.peek().is_some_and(|c| c.is_ascii_alphanumeric()) => {
+                    let mut name = String::new();
+                    while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                        name.push(c);
+                    }
+                    nginx_variable(&name)
+                }
+                _ => {
+                    pattern.push_str(&regex::escape(&c.to_string()));
+                    continue;
+                }
+            };
+            match field {
+                // %t includes the brackets, unlike $time_local
+                Field::ClfTime if c == '%' => pattern.push_str(r"\[(.*?)\]"),
+                _ => pattern.push_str("(.*?)"),
+            }
+            fields.push(field);
+        }
+        pattern.push('$');
+        if !fields.contains(&Field::Client) && !fields.contains(&Field::ForwardedFor) {
+            return Err(format!("Log format has no client address: {}", format));
+        }
+        let pattern = Regex::new(&pattern).map_err(|e| format!("Invalid log format: {}", e))?;
+        Ok(LogFormat { pattern, fields })
+    }
+}
+
+fn apache_directive(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<Field, String> {
+    while chars.next_if(|c| *c == '>' || *c == '<').is_some() {}
+    let mut name = String::new();
+    if chars.next_if_eq(&'{').is_some() {
+        for c in chars.by_ref() {
+            if c == '}' {
+                break;
+            }
+            name.push(c);
+        }
+    }
+    let directive = chars
+        .next()
+        .ok_or_else(|| String::from("Log format ends with %"))?;
+    Ok(match directive {
+        'h' | 'a' => Field::Client,
+        't' => Field::ClfTime,
+        'r' => Field::Request,
+        's' => Field::Status,
+        'i' if name.eq_ignore_ascii_case("x-forwarded-for") => Field::ForwardedFor,
+        _ => Field::Other,
+    })
+}
+
+fn nginx_variable(name: &str) -> Field {
+    match name {
+        "remote_addr" | "realip_remote_addr" => Field::Client,
+        "time_local" => Field::ClfTime,
+        "time_iso8601" => Field::IsoTime,
+        "request" => Field::Request,
+        "status" => Field::Status,
+        "http_x_forwarded_for" => Field::ForwardedFor,
+        _ => Field::Other,
+    }
+}
+
+impl LogFormat {
+    /// The client and `X-Forwarded-For` addresses of a log line, with the
+    /// timestamp, method, path and status of the request as context.
+    /// Timestamps are converted to UTC.
+    pub fn parse_line(&self, line: &str) -> Vec<Sighting> {
+        let captures = match self.pattern.captures(line) {
+            Some(captures) => captures,
+            None => return vec![],
+        };
+        let mut context: Vec<(&str, String)> = vec![];
+        let mut addresses: Vec<(IpAddr, &str)> = vec![];
+        for (field, value) in self.fields.iter().zip(captures.iter().skip(1)) {
+            let value = match value.map(|v| v.as_str().trim()) {
+                Some(value) if !value.is_empty() && value != "-" => value,
+                _ => continue,
+            };
+            match field {
+                Field::Client => {
+                    if let Ok(ip) = value.parse::<IpAddr>() {
+                        addresses.push((ip, "client"));
+                    }
+                }
+                Field::ForwardedFor => {
+                    for hop in value.split(',') {
+                        if let Ok(ip) = hop.trim().parse::<IpAddr>() {
+                            addresses.push((ip, "x_forwarded_for"));
+                        }
+                    }
+                }
+                Field::ClfTime => {
+                    let timestamp = DateTime::parse_from_str(value, "%d/%b/%Y:%H:%M:%S %z")
+                        .map(|t| utc_timestamp(&t))
+                        .unwrap_or_else(|_| value.to_string());
+                    context.push(("timestamp", timestamp));
+                }
+                Field::IsoTime => {
+                    let timestamp = DateTime::parse_from_rfc3339(value)
+                        .map(|t| utc_timestamp(&t))
+                        .unwrap_or_else(|_| value.to_string());
+                    context.push(("timestamp", timestamp));
+                }
+                Field::Request => {
+                    let mut parts = value.split_whitespace();
+                    if let (Some(method), Some(path)) = (parts.next(), parts.next()) {
+                        context.push(("method", method.to_string()));
+                        context.push(("path", path.to_string()));
+                    }
+                }
+                Field::Status => context.push(("status", value.to_string())),
+                Field::Other => (),
+            }
+        }
+        addresses
+            .into_iter()
+            .map(|(ip, field)| {
+                context.iter().fold(
+                    Sighting::new(ip).with("field", field),
+                    |sighting, (k, v)| sighting.with(k, v.as_str()),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Extract the client and forwarded addresses of an access log, which may
+/// be gzipped
+pub fn parse_access_log_file(file_path: &Path, format: &LogFormat) -> Result<Vec<Sighting>> {
+    parse_access_log_reader(open_reader(file_path)?, format)
+}
+
+/// Extract the client and forwarded addresses of each line of an access
+/// log. Lines that do not match the format are skipped.
+pub fn parse_access_log_reader<R: Read>(reader: R, format: &LogFormat) -> Result<Vec<Sighting>> {
+    let mut reader = BufReader::new(reader);
+    let mut sightings = vec![];
+    let mut line = vec![];
+    loop {
+        line.clear();
+        let read = reader
+            .read_until(b'\n', &mut line)
+            .map_err(|e| anyhow!("Unable to read access log: {}", e))?;
+        if read == 0 {
+            break;
+        }
+        let text = String::from_utf8_lossy(&line);
+        sightings.extend(format.parse_line(text.trim_end_matches(['\r', '\n'])));
+    }
+    Ok(sightings)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn context(sighting: &Sighting) -> Vec<(&str, &str)> {
+        sighting
+            .context
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_combined() {
+        let format = "combined".parse::<LogFormat>().unwrap();
+        let line = r#"203.0.113.9 - frank [10/Oct/2000:13:55:36 -0700] "GET /proxy/10.0.0.1/status HTTP/1.0" 200 2326 "http://198.51.100.1/" "Mozilla/4.08""#;
+        let sightings = format.parse_line(line);
+        assert_eq!(1, sightings.len());
+        assert_eq!("203.0.113.9", sightings[0].ip.to_string());
+        assert_eq!(
+            vec![
+                ("field", "client"),
+                ("method", "GET"),
+                ("path", "/proxy/10.0.0.1/status"),
+                ("status", "200"),
+                ("timestamp", "2000-10-10T20:55:36Z"),
+            ],
+            context(&sightings[0])
+        );
+    }
+
+    #[test]
+    fn test_nginx_forwarded_for() {
+        let format = r#"$remote_addr - $remote_user [$time_local] "$request" $status $body_bytes_sent "$http_referer" "$http_user_agent" "$http_x_forwarded_for""#
+            .parse::<LogFormat>()
+            .unwrap();
+        let line = r#"10.0.0.2 - - [01/Apr/2023:10:00:00 +0000] "POST /login HTTP/1.1" 401 12 "-" "curl/8.0" "198.51.100.7, 2001:db8::5""#;
+        let sightings = format.parse_line(line);
+        let found: Vec<(String, &str)> = sightings
+            .iter()
+            .map(|s| (s.ip.to_string(), s.context["field"].as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                (String::from("10.0.0.2"), "client"),
+                (String::from("198.51.100.7"), "x_forwarded_for"),
+                (String::from("2001:db8::5"), "x_forwarded_for"),
+            ],
+            found
+        );
+        assert_eq!("401", sightings[2].context["status"]);
+        assert_eq!("2023-04-01T10:00:00Z", sightings[2].context["timestamp"]);
+    }
+
+    #[test]
+    fn test_iso_time_and_unmatched_lines() {
+        let format = "$time_iso8601 $remote_addr $status"
+            .parse::<LogFormat>()
+            .unwrap();
+        let input = "2023-04-01T12:00:00+02:00 192.0.2.1 200\nnot a log line\n";
+        let sightings = parse_access_log_reader(input.as_bytes(), &format).unwrap();
+        assert_eq!(1, sightings.len());
+        assert_eq!("2023-04-01T10:00:00Z", sightings[0].context["timestamp"]);
+    }
+
+    #[test]
+    fn test_invalid_utf8_line() {
+        let format = "common".parse::<LogFormat>().unwrap();
+        let input = b"192.0.2.1 - - [01/Apr/2023:10:00:00 +0000] \"GET / HTTP/1.1\" 200 1\n\
+            192.0.2.2 - - [01/Apr/2023:10:00:01 +0000] \"GET /caf\xe9 HTTP/1.1\" 404 1\n\
+            192.0.2.3 - - [01/Apr/2023:10:00:02 +0000] \"GET / HTTP/1.1\" 200 1\n";
+        let sightings = parse_access_log_reader(&input[..], &format).unwrap();
+        let ips: Vec<String> = sightings.iter().map(|s| s.ip.to_string()).collect();
+        assert_eq!(vec!["192.0.2.1", "192.0.2.2", "192.0.2.3"], ips);
+    }
+
+    #[test]
+    fn test_invalid_format() {
+        assert!("%t \"%r\"".parse::<LogFormat>().is_err());
+        assert!("%h %".parse::<LogFormat>().is_err());
+    }
+}
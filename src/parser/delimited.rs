@@ -0,0 +1,186 @@
+use crate::parser::{open_reader, HeaderMode, Sighting};
+use crate::util::get_all_ips;
+use anyhow::{anyhow, Result};
+use std::io::Read;
+use std::path::Path;
+
+/// Extract the addresses from the given columns of a delimited file,
+/// which may be gzipped
+pub fn parse_delimited_file(
+    file_path: &Path,
+    delimiter: u8,
+    header: HeaderMode,
+    columns: &[String],
+) -> Result<Vec<Sighting>> {
+    parse_delimited_reader(open_reader(file_path)?, delimiter, header, columns)
+}
+
+/// Extract the addresses from the given columns of CSV or TSV data.
+///
+/// Columns are named by their header, or numbered from 1. Every column is
+/// searched when no columns are given. When detecting the header, the
+/// first row is a header if none of its cells hold an address. Each
+/// address records its column name, or number without a header, as
+/// `field` context.
+pub fn parse_delimited_reader<R: Read>(
+    reader: R,
+    delimiter: u8,
+    header: HeaderMode,
+    columns: &[String],
+) -> Result<Vec<Sighting>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(reader);
+    let mut rows = reader.records().peekable();
+
+    let names: Option<Vec<String>> = match rows.peek() {
+        Some(Ok(first)) => {
+            let is_header = match header {
+                HeaderMode::Present => true,
+                HeaderMode::Absent => false,
+                HeaderMode::Detect => first.iter().all(|cell| get_all_ips(cell).is_empty()),
+            };
+            is_header.then(|| first.iter().map(|cell| cell.trim().to_string()).collect())
+        }
+        _ => None,
+    };
+    if names.is_some() {
+        rows.next();
+    }
+    let column_name = |index: usize| match &names {
+        Some(names) => names
+            .get(index)
+            .cloned()
+            .unwrap_or_else(|| (index + 1).to_string()),
+        None => (index + 1).to_string(),
+    };
+
+    let indexes = column_indexes(names.as_deref(), columns)?;
+    let mut sightings = vec![];
+    for row in rows {
+        let row = row.map_err(|e| anyhow!("Invalid delimited data: {}", e))?;
+        for (index, cell) in row.iter().enumerate() {
+            if indexes.as_ref().is_some_and(|i| !i.contains(&index)) {
+                continue;
+            }
+            for ip in get_all_ips(cell) {
+                sightings.push(Sighting::new(ip).with("field", column_name(index)));
+            }
+        }
+    }
+    Ok(sightings)
+}
+
+/// Zero-based indexes of the requested columns, or None to search them all
+fn column_indexes(names: Option<&[String]>, columns: &[String]) -> Result<Option<Vec<usize>>> {
+    if columns.is_empty() {
+        return Ok(None);
+    }
+    columns
+        .iter()
+        .map(|column| {
+            let by_name = names.and_then(|names| names.iter().position(|name| name == column));
+            let by_number = column
+                .parse::<usize>()
+                .ok()
+                .filter(|n| *n > 0)
+                .map(|n| n - 1);
+            by_name
+                .or(by_number)
+                .ok_or_else(|| anyhow!("Unknown column {}", column))
+        })
+        .collect::<Result<Vec<usize>>>()
+        .map(Some)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FIREWALL: &str = "time,src,dst,action\n\
+        2023-04-01T10:00:00Z,10.0.0.5,198.51.100.7,allow\n\
+        2023-04-01T10:00:01Z,10.0.0.6,\"203.0.113.9, 192.0.2.1\",deny\n";
+
+    fn found(sightings: &[Sighting]) -> Vec<(String, String)> {
+        sightings
+            .iter()
+            .map(|s| (s.ip.to_string(), s.context["field"].clone()))
+            .collect()
+    }
+
+    fn pairs(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+        expected
+            .iter()
+            .map(|(ip, field)| (ip.to_string(), field.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_named_column() {
+        let sightings = parse_delimited_reader(
+            FIREWALL.as_bytes(),
+            b',',
+            HeaderMode::Detect,
+            &[String::from("dst")],
+        )
+        .unwrap();
+        assert_eq!(
+            pairs(&[
+                ("198.51.100.7", "dst"),
+                ("203.0.113.9", "dst"),
+                ("192.0.2.1", "dst")
+            ]),
+            found(&sightings)
+        );
+    }
+
+    #[test]
+    fn test_parse_all_columns() {
+        let sightings =
+            parse_delimited_reader(FIREWALL.as_bytes(), b',', HeaderMode::Detect, &[]).unwrap();
+        assert_eq!(5, sightings.len());
+        assert_eq!(pairs(&[("10.0.0.5", "src")])[0], found(&sightings)[0]);
+    }
+
+    #[test]
+    fn test_parse_tsv_without_header() {
+        let input = "10.0.0.5\t8.8.8.8\n10.0.0.6\t1.1.1.1\n";
+        let sightings = parse_delimited_reader(
+            input.as_bytes(),
+            b'\t',
+            HeaderMode::Detect,
+            &[String::from("2")],
+        )
+        .unwrap();
+        assert_eq!(
+            pairs(&[("8.8.8.8", "2"), ("1.1.1.1", "2")]),
+            found(&sightings)
+        );
+    }
+
+    #[test]
+    fn test_parse_header_modes() {
+        let input = "8.8.8.8,source\n1.1.1.1,x\n";
+        let present =
+            parse_delimited_reader(input.as_bytes(), b',', HeaderMode::Present, &[]).unwrap();
+        assert_eq!(pairs(&[("1.1.1.1", "8.8.8.8")]), found(&present));
+
+        let input = "src,dst\n";
+        let absent =
+            parse_delimited_reader(input.as_bytes(), b',', HeaderMode::Absent, &[]).unwrap();
+        assert!(absent.is_empty());
+    }
+
+    #[test]
+    fn test_parse_unknown_column() {
+        let error = parse_delimited_reader(
+            FIREWALL.as_bytes(),
+            b',',
+            HeaderMode::Detect,
+            &[String::from("source")],
+        );
+        assert_eq!("Unknown column source", error.unwrap_err().to_string());
+    }
+}
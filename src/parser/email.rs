@@ -0,0 +1,334 @@
+use crate::parser::{open_reader, utc_timestamp, Sighting};
+use crate::util::get_all_ips;
+use anyhow::{anyhow, Result};
+use chrono::DateTime;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::io::{BufRead, BufReader, Read};
+use std::net::IpAddr;
+use std::path::Path;
+
+lazy_static! {
+    // from HOST (COMMENTS) by HOST, where the comments hold the address the
+    // sending relay connected from
+    static ref RECEIVED: Regex =
+        Regex::new(r"(?i)^\s*from\s+(\S+)(.*?)\s+by\s+([^\s;]+)").unwrap();
+    static ref COMMENT: Regex = Regex::new(r"\([^()]*\)").unwrap();
+    // An address literal, such as [192.0.2.1] or [IPv6:2001:db8::1]
+    static ref LITERAL: Regex = Regex::new(r"(?i)\[(?:IPv6:)?([0-9a-f:.]+)\]").unwrap();
+    // The HELO name that Exim and others record inside the comments
+    static ref HELO: Regex = Regex::new(r"(?i)\b(?:helo|ehlo)=\S+").unwrap();
+}
+
+/// The addresses the relay connected from, and whether they were taken
+/// from the TCP information of the comments or, lacking any, from the
+/// HELO name the sender chose.
+///
+/// Address literals in the comments, such as `(unknown [198.51.100.7])`,
+/// are preferred to bare addresses, such as Exchange's `(2001:db8::5)`.
+fn relay_addresses(from_host: &str, comments: &str) -> (Vec<IpAddr>, &'static str) {
+    let comments = HELO.replace_all(comments, "");
+    let literals: Vec<IpAddr> = LITERAL
+        .captures_iter(&comments)
+        .filter_map(|c| c[1].parse().ok())
+        .collect();
+    if !literals.is_empty() {
+        return (literals, "tcp_info");
+    }
+    let bare = get_all_ips(&comments);
+    if !bare.is_empty() {
+        return (bare, "tcp_info");
+    }
+    (get_all_ips(&from_host.replace("IPv6:", "")), "helo")
+}
+
+/// The value of each header, unfolded, in the order they appear
+struct Headers(Vec<(String, String)>);
+
+impl Headers {
+    fn all<'a>(&'a self, name: &'a str) -> impl DoubleEndedIterator<Item = &'a str> + 'a {
+        self.0
+            .iter()
+            .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn first<'a>(&'a self, name: &'a str) -> Option<&'a str> {
+        self.all(name).next()
+    }
+}
+
+/// An RFC 2822 date, ignoring comments such as `(UTC)`
+fn timestamp(date: &str) -> Option<String> {
+    let date = COMMENT.replace_all(date, "");
+    DateTime::parse_from_rfc2822(date.trim())
+        .ok()
+        .map(|t| utc_timestamp(&t))
+}
+
+/// The relaying addresses of the `Received` headers, from the first hop to
+/// the last, and the `X-Originating-IP` of the message
+fn message_sightings(headers: &Headers) -> Vec<Sighting> {
+    let message_id = headers.first("Message-ID").map(str::trim);
+    let with_message_id = |sighting: Sighting| match message_id {
+        Some(message_id) => sighting.with("message_id", message_id),
+        None => sighting,
+    };
+    let mut sightings = vec![];
+
+    // Each relay adds its header above the others, so the first hop is last.
+    // Headers without a from clause, such as local delivery, are not hops.
+    let hops = headers
+        .all("Received")
+        .rev()
+        .filter_map(|received| Some((received, RECEIVED.captures(received)?)));
+    for (index, (received, captures)) in hops.enumerate() {
+        let (date, timestamp) = match received.rsplit_once(';') {
+            Some((_, date)) => (Some(date), timestamp(date)),
+            None => (None, None),
+        };
+        let (ips, source) = relay_addresses(&captures[1], &captures[2]);
+        for ip in ips {
+            let mut sighting = Sighting::new(ip)
+                .with("field", "received")
+                .with("source", source)
+                .with("hop", (index + 1).to_string())
+                .with("from_host", &captures[1])
+                .with("by_host", &captures[3]);
+            if let Some(timestamp) = &timestamp {
+                sighting = sighting.with("timestamp", timestamp.as_str());
+            } else if let Some(date) = date {
+                sighting = sighting.with("timestamp", date.trim());
+            }
+            sightings.push(with_message_id(sighting));
+        }
+    }
+
+    let date = headers.first("Date").and_then(timestamp);
+    for value in headers.all("X-Originating-IP") {
+        for ip in get_all_ips(&value.replace("IPv6:", "")) {
+            let mut sighting = Sighting::new(ip).with("field", "x_originating_ip");
+            if let Some(date) = &date {
+                sighting = sighting.with("timestamp", date.as_str());
+            }
+            sightings.push(with_message_id(sighting));
+        }
+    }
+    sightings
+}
+
+/// Extract the relaying addresses from the headers of an email or mbox
+/// file, which may be gzipped
+pub fn parse_email_file(file_path: &Path) -> Result<Vec<Sighting>> {
+    parse_email_reader(open_reader(file_path)?)
+}
+
+/// Extract the relaying addresses from the headers of each message of an
+/// `.eml` file or mbox.
+///
+/// The `Received` chain is walked from the first hop to the last, and each
+/// address in a `from` clause records its `hop`, starting from 1, the
+/// `from_host` and `by_host` names and the `timestamp` of the hop. The
+/// address is taken from the TCP information the relay recorded, and only
+/// from the sender's HELO name when there is none, with `source` telling
+/// which. Addresses in `X-Originating-IP` headers are also extracted.
+/// Message bodies are not searched.
+pub fn parse_email_reader<R: Read>(reader: R) -> Result<Vec<Sighting>> {
+    let mut reader = BufReader::new(reader);
+    let mut sightings = vec![];
+    let mut headers = Headers(vec![]);
+    let mut in_headers = true;
+    let mut previous_blank = true;
+    let mut line = vec![];
+    loop {
+        line.clear();
+        let read = reader
+            .read_until(b'\n', &mut line)
+            .map_err(|e| anyhow!("Unable to read email: {}", e))?;
+        if read == 0 {
+            break;
+        }
+        let text = String::from_utf8_lossy(&line);
+        let text = text.trim_end_matches(['\r', '\n']);
+
+        // An mbox separator line starts the next message
+        if previous_blank && text.starts_with("From ") {
+            sightings.extend(message_sightings(&headers));
+            headers.0.clear();
+            in_headers = true;
+            previous_blank = false;
+            continue;
+        }
+        previous_blank = text.is_empty();
+        if !in_headers {
+            continue;
+        }
+        if text.is_empty() {
+            in_headers = false;
+        } else if text.starts_with([' ', '\t']) {
+            // A folded continuation of the previous header
+            if let Some((_, value)) = headers.0.last_mut() {
+                value.push(' ');
+                value.push_str(text.trim());
+            }
+        } else if let Some((name, value)) = text.split_once(':') {
+            headers
+                .0
+                .push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    sightings.extend(message_sightings(&headers));
+    Ok(sightings)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PHISH: &str = "Received: from mx.corp.example (mx.corp.example [10.0.0.25])\r
+\tby mail.corp.example with ESMTPS id 4Pq; Sat, 1 Apr 2023 10:00:05 +0000 (UTC)\r
+Received: from smtp.bad.example (unknown [198.51.100.7])\r
+\tby mx.corp.example (Postfix) with ESMTP id 9A1;\r
+\tSat, 1 Apr 2023 11:59:59 +0200\r
+Received: from [IPv6:2001:db8::5] (helo=laptop) by smtp.bad.example with esmtpa;\r
+ Sat, 01 Apr 2023 09:59:00 +0000\r
+X-Originating-IP: [203.0.113.9]\r
+Date: Sat, 1 Apr 2023 09:58:00 +0000\r
+Message-ID: <abc@bad.example>\r
+Subject: Invoice 192.0.2.1\r
+\r
+Please pay 192.0.2.2\r
+";
+
+    fn hops(sightings: &[Sighting]) -> Vec<(String, String)> {
+        sightings
+            .iter()
+            .map(|s| {
+                let hop = s.context.get("hop").cloned().unwrap_or_default();
+                (s.ip.to_string(), hop)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_received_chain() {
+        let sightings = parse_email_reader(PHISH.as_bytes()).unwrap();
+        assert_eq!(
+            vec![
+                (String::from("2001:db8::5"), String::from("1")),
+                (String::from("198.51.100.7"), String::from("2")),
+                (String::from("10.0.0.25"), String::from("3")),
+                (String::from("203.0.113.9"), String::new()),
+            ],
+            hops(&sightings)
+        );
+        let context: Vec<(&str, &str)> = sightings[1]
+            .context
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("by_host", "mx.corp.example"),
+                ("field", "received"),
+                ("from_host", "smtp.bad.example"),
+                ("hop", "2"),
+                ("message_id", "<abc@bad.example>"),
+                ("source", "tcp_info"),
+                ("timestamp", "2023-04-01T09:59:59Z"),
+            ],
+            context
+        );
+        assert_eq!("helo", sightings[0].context["source"]);
+        assert_eq!("x_originating_ip", sightings[3].context["field"]);
+        assert_eq!("2023-04-01T09:58:00Z", sightings[3].context["timestamp"]);
+    }
+
+    #[test]
+    fn test_spoofed_helo() {
+        let eml = "Received: from [10.1.1.1] (mail.example [198.51.100.7])\n\
+            \tby mx.example; Sat, 1 Apr 2023 10:00:00 +0000\n\
+            Received: from 192.0.2.50 (helo=192.0.2.51) by mx.example; Sat, 1 Apr 2023 10:00:00 +0000\n\
+            \n";
+        let sightings = parse_email_reader(eml.as_bytes()).unwrap();
+        let found: Vec<(String, &str)> = sightings
+            .iter()
+            .map(|s| (s.ip.to_string(), s.context["source"].as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                (String::from("192.0.2.50"), "helo"),
+                (String::from("198.51.100.7"), "tcp_info"),
+            ],
+            found
+        );
+    }
+
+    #[test]
+    fn test_relay_addresses() {
+        let ips = |(ips, source): (Vec<IpAddr>, &'static str)| {
+            let ips: Vec<String> = ips.iter().map(IpAddr::to_string).collect();
+            (ips.join(","), source)
+        };
+        assert_eq!(
+            (String::from("2001:db8::5"), "tcp_info"),
+            ips(relay_addresses(
+                "x",
+                " ([IPv6:2001:db8::5] helo=[192.0.2.9])"
+            ))
+        );
+        assert_eq!(
+            (String::from("2603:10b6::12"), "tcp_info"),
+            ips(relay_addresses("BN6PR", " (2603:10b6::12)"))
+        );
+        assert_eq!(
+            (String::from("192.0.2.1"), "tcp_info"),
+            ips(relay_addresses(
+                "a.example",
+                " (a.example [192.0.2.1] (may be forged))"
+            ))
+        );
+        assert_eq!(
+            (String::new(), "helo"),
+            ips(relay_addresses("localhost", ""))
+        );
+    }
+
+    #[test]
+    fn test_hops_without_from() {
+        let eml = "Received: from b.example ([192.0.2.20]) by mx.example; Sat, 1 Apr 2023 11:00:00 +0000\n\
+            Received: by local.example; Sat, 1 Apr 2023 10:30:00 +0000\n\
+            Received: from a.example ([192.0.2.10]) by local.example; Sat, 1 Apr 2023 10:00:00 +0000\n\
+            \n";
+        let sightings = parse_email_reader(eml.as_bytes()).unwrap();
+        assert_eq!(
+            vec![
+                (String::from("192.0.2.10"), String::from("1")),
+                (String::from("192.0.2.20"), String::from("2")),
+            ],
+            hops(&sightings)
+        );
+    }
+
+    #[test]
+    fn test_mbox() {
+        let mbox = "From alice@example.com Sat Apr  1 10:00:00 2023\n\
+            Received: from a.example ([192.0.2.10]) by mx.example; Sat, 1 Apr 2023 10:00:00 +0000\n\
+            \n\
+            From the desk of 192.0.2.99\n\
+            \n\
+            From bob@example.com Sat Apr  1 11:00:00 2023\n\
+            Received: from b.example ([192.0.2.20]) by mx.example; Sat, 1 Apr 2023 11:00:00 +0000\n\
+            Received: by local.example; Sat, 1 Apr 2023 11:00:00 +0000\n\
+            \n\
+            body\n";
+        let sightings = parse_email_reader(mbox.as_bytes()).unwrap();
+        assert_eq!(
+            vec![
+                (String::from("192.0.2.10"), String::from("1")),
+                (String::from("192.0.2.20"), String::from("1")),
+            ],
+            hops(&sightings)
+        );
+    }
+}
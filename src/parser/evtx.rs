@@ -0,0 +1,248 @@
+use crate::parser::{determine_file_type, open_reader, utc_timestamp, Sighting, SourceFileType};
+use crate::util::get_all_ips;
+use anyhow::{anyhow, Result};
+use chrono::DateTime;
+use evtx::EvtxParser;
+use serde_json::Value;
+use std::io::{Read, Seek};
+use std::net::IpAddr;
+use std::path::Path;
+
+/// The first bytes of an EVTX file
+pub const MAGIC: &[u8] = b"ElfFile\x00";
+
+/// Whether an event data field holds addresses, such as `IpAddress`,
+/// `SourceAddress`, `DestAddress` or `ClientIP`
+fn is_address_field(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name.ends_with("address") || name.ends_with("addr") || name.ends_with("ip")
+}
+
+/// A number or string value, which may be wrapped with attributes as
+/// `{"#attributes": ..., "#text": value}`
+fn text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.to_string()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Object(map) => map.get("#text").and_then(text),
+        _ => None,
+    }
+}
+
+/// Search the named fields of event data. Wrapped values are searched
+/// under the name of their field, as are the `#text` arrays of unnamed
+/// `Data` elements.
+fn find_in_data(
+    value: &Value,
+    fields: &[String],
+    name: &str,
+    found: &mut Vec<(String, Vec<IpAddr>)>,
+) {
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map {
+                if key == "#text" {
+                    find_in_data(child, fields, name, found);
+                } else if !key.starts_with('#') {
+                    find_in_data(child, fields, key, found);
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                find_in_data(item, fields, name, found);
+            }
+        }
+        _ => {
+            let wanted = if fields.is_empty() {
+                is_address_field(name)
+            } else {
+                fields.iter().any(|field| field == name)
+            };
+            if let (true, Some(text)) = (wanted, text(value)) {
+                found.push((name.to_string(), get_all_ips(&text)));
+            }
+        }
+    }
+}
+
+/// The addresses in the event data of a record, as serialized to JSON by
+/// the `evtx` crate, with its event id, computer and time as context
+fn event_sightings(record: &Value, fields: &[String]) -> Vec<Sighting> {
+    let event = record.get("Event").unwrap_or(record);
+    let system = event.get("System");
+    let event_id = system.and_then(|s| s.get("EventID")).and_then(text);
+    let computer = system.and_then(|s| s.get("Computer")).and_then(text);
+    let timestamp = system
+        .and_then(|s| s.pointer("/TimeCreated/#attributes/SystemTime"))
+        .and_then(Value::as_str)
+        .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+        .map(|t| utc_timestamp(&t));
+
+    let mut found = vec![];
+    for section in ["EventData", "UserData"] {
+        if let Some(data) = event.get(section) {
+            find_in_data(data, fields, section, &mut found);
+        }
+    }
+    found
+        .into_iter()
+        .flat_map(|(field, ips)| ips.into_iter().map(move |ip| (field.clone(), ip)))
+        .map(|(field, ip)| {
+            let mut sighting = Sighting::new(ip).with("field", field);
+            for (key, value) in [
+                ("event_id", &event_id),
+                ("computer", &computer),
+                ("timestamp", &timestamp),
+            ] {
+                if let Some(value) = value {
+                    sighting = sighting.with(key, value.as_str());
+                }
+            }
+            sighting
+        })
+        .collect()
+}
+
+/// Extract the addresses from the event data of each record of a Windows
+/// event log, which may be gzipped.
+///
+/// Fields whose names end with `Address`, `Addr` or `IP` are read unless
+/// fields are given. Each address records its field and the `event_id`,
+/// `computer` and `timestamp` of its event as context.
+pub fn parse_evtx_file(file_path: &Path, fields: &[String]) -> Result<Vec<Sighting>> {
+    if determine_file_type(file_path)? == SourceFileType::Gzip {
+        let mut buffer = vec![];
+        open_reader(file_path)?.read_to_end(&mut buffer)?;
+        Ok(parse_records(
+            EvtxParser::from_buffer(buffer).map_err(invalid)?,
+            fields,
+        ))
+    } else {
+        Ok(parse_records(
+            EvtxParser::from_path(file_path).map_err(invalid)?,
+            fields,
+        ))
+    }
+}
+
+fn invalid(e: evtx::err::EvtxError) -> anyhow::Error {
+    anyhow!("Invalid EVTX file: {}", e)
+}
+
+/// The sightings of each record of the log as it is read, skipping any
+/// records that are corrupt
+fn parse_records<T: Read + Seek>(mut parser: EvtxParser<T>, fields: &[String]) -> Vec<Sighting> {
+    let mut sightings = vec![];
+    for record in parser.records_json_value() {
+        match record {
+            Ok(record) => sightings.extend(event_sightings(&record.data, fields)),
+            Err(e) => eprintln!("Skipping invalid EVTX record: {}", e),
+        }
+    }
+    sightings
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn logon_failure() -> Value {
+        json!({
+            "Event": {
+                "#attributes": {"xmlns": "http://schemas.microsoft.com/win/2004/08/events/event"},
+                "System": {
+                    "EventID": 4625,
+                    "TimeCreated": {"#attributes": {"SystemTime": "2023-04-01T10:00:00.123456Z"}},
+                    "Computer": "DC01.corp.example"
+                },
+                "EventData": {
+                    "TargetUserName": "administrator",
+                    "WorkstationName": "KALI",
+                    "IpAddress": "203.0.113.9",
+                    "IpPort": "52144",
+                    "ProcessName": "C:\\Windows\\System32\\1.2.3.4.exe"
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn test_event_sightings() {
+        let sightings = event_sightings(&logon_failure(), &[]);
+        assert_eq!(1, sightings.len());
+        assert_eq!("203.0.113.9", sightings[0].ip.to_string());
+        let context: Vec<(&str, &str)> = sightings[0]
+            .context
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("computer", "DC01.corp.example"),
+                ("event_id", "4625"),
+                ("field", "IpAddress"),
+                ("timestamp", "2023-04-01T10:00:00Z"),
+            ],
+            context
+        );
+    }
+
+    #[test]
+    fn test_event_sightings_user_data() {
+        // TerminalServices-LocalSessionManager records the RDP client
+        let record = json!({
+            "Event": {
+                "System": {
+                    "EventID": {"#attributes": {"Qualifiers": ""}, "#text": 25},
+                    "Computer": "WS01"
+                },
+                "UserData": {
+                    "EventXML": {"User": "CORP\\alice", "SessionID": 2, "Address": "198.51.100.7"}
+                }
+            }
+        });
+        let sightings = event_sightings(&record, &[]);
+        assert_eq!(1, sightings.len());
+        assert_eq!("25", sightings[0].context["event_id"]);
+        assert_eq!("Address", sightings[0].context["field"]);
+    }
+
+    #[test]
+    fn test_event_sightings_fields() {
+        let fields = vec![String::from("ProcessName")];
+        let sightings = event_sightings(&logon_failure(), &fields);
+        assert_eq!("1.2.3.4", sightings[0].ip.to_string());
+    }
+
+    #[test]
+    fn test_event_sightings_text() {
+        let record = json!({
+            "Event": {
+                "EventData": {
+                    "SourceAddress": {"#attributes": {"Type": "ip"}, "#text": "192.0.2.1"},
+                    "Data": {"#text": ["svc", "198.51.100.7"]}
+                }
+            }
+        });
+        let sightings = event_sightings(&record, &[]);
+        assert_eq!(1, sightings.len());
+        assert_eq!("192.0.2.1", sightings[0].ip.to_string());
+        assert_eq!("SourceAddress", sightings[0].context["field"]);
+
+        let sightings = event_sightings(&record, &[String::from("Data")]);
+        assert_eq!(1, sightings.len());
+        assert_eq!("198.51.100.7", sightings[0].ip.to_string());
+        assert_eq!("Data", sightings[0].context["field"]);
+    }
+
+    #[test]
+    fn test_not_evtx() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"1.1.1.1").unwrap();
+        assert!(parse_evtx_file(file.path(), &[]).is_err());
+    }
+}
@@ -0,0 +1,290 @@
+use crate::parser::{open_reader, Sighting};
+use crate::util::get_all_ips;
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+/// The text of the documents read so far that have not been parsed, with
+/// the nesting of brackets outside of strings
+#[derive(Default)]
+struct Pending {
+    text: String,
+    /// Line number the text starts on, counting from 1
+    line: usize,
+    depth: i64,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl Pending {
+    fn push(&mut self, line: &str) {
+        for c in line.chars() {
+            if self.in_string {
+                match c {
+                    _ if self.escaped => self.escaped = false,
+                    '\\' => self.escaped = true,
+                    '"' => self.in_string = false,
+                    _ => (),
+                }
+            } else {
+                match c {
+                    '"' => self.in_string = true,
+                    '{' | '[' => self.depth += 1,
+                    '}' | ']' => self.depth -= 1,
+                    _ => (),
+                }
+            }
+        }
+        self.text.push_str(line);
+        self.text.push('\n');
+    }
+
+    /// Whether the brackets are balanced, or the text is already invalid
+    /// as strings cannot span lines
+    fn is_complete(&self) -> bool {
+        self.depth <= 0 || self.in_string
+    }
+
+    /// Parse the documents in the text, skipping the rest of it from the
+    /// first invalid document
+    fn take_documents(&mut self) -> Vec<Value> {
+        let mut documents = vec![];
+        for document in serde_json::Deserializer::from_str(&self.text).into_iter::<Value>() {
+            match document {
+                Ok(document) => documents.push(document),
+                Err(e) => {
+                    eprintln!(
+                        "Skipping invalid JSON at line {}: {}",
+                        self.line + e.line().saturating_sub(1),
+                        e
+                    );
+                    break;
+                }
+            }
+        }
+        *self = Pending::default();
+        documents
+    }
+
+    /// Drop an unfinished document, such as the last of a truncated file
+    fn skip_truncated(&mut self) {
+        if !self.text.trim().is_empty() {
+            eprintln!("Skipping truncated JSON at line {}", self.line);
+        }
+        *self = Pending::default();
+    }
+}
+
+/// Extract the addresses from the given fields of a JSON lines file,
+/// which may be gzipped
+pub fn parse_json_file(file_path: &Path, fields: &[String]) -> Result<Vec<Sighting>> {
+    parse_json_reader(open_reader(file_path)?, fields)
+}
+
+/// Extract the addresses from the given fields of each JSON document.
+///
+/// Fields are dot-separated paths, such as `client.ipAddress`, and arrays
+/// along the path are searched element by element. Every string field is
+/// searched when no fields are given. Each address records the field it
+/// was found in as `field` context.
+///
+/// Invalid documents are skipped with a warning naming their line. A line
+/// starting with `{` or `[` starts a new document, ending any unfinished
+/// one, so a truncated line of JSON lines does not hide the next.
+pub fn parse_json_reader<R: Read>(reader: R, fields: &[String]) -> Result<Vec<Sighting>> {
+    let mut reader = BufReader::new(reader);
+    let mut sightings = vec![];
+    let mut pending = Pending::default();
+    let mut bytes = vec![];
+    let mut line_number = 0;
+    loop {
+        bytes.clear();
+        let read = reader
+            .read_until(b'\n', &mut bytes)
+            .map_err(|e| anyhow!("Unable to read JSON: {}", e))?;
+        if read == 0 {
+            break;
+        }
+        line_number += 1;
+        let line = String::from_utf8_lossy(&bytes);
+        let line = line.trim_end_matches(['\r', '\n']);
+
+        if !pending.text.is_empty() && line.starts_with(['{', '[']) {
+            pending.skip_truncated();
+        }
+        if pending.text.is_empty() {
+            pending.line = line_number;
+        }
+        pending.push(line);
+        if pending.is_complete() {
+            for document in pending.take_documents() {
+                find_in_document(&document, fields, &mut sightings);
+            }
+        }
+    }
+    pending.skip_truncated();
+    Ok(sightings)
+}
+
+fn find_in_document(document: &Value, fields: &[String], sightings: &mut Vec<Sighting>) {
+    if fields.is_empty() {
+        find_in_leaves(document, "", sightings);
+        return;
+    }
+    for field in fields {
+        let path: Vec<&str> = field.split('.').collect();
+        let mut values = vec![];
+        lookup(document, &path, &mut values);
+        for value in values {
+            find_in_value(value, field, sightings);
+        }
+    }
+}
+
+pub(crate) fn lookup<'a>(value: &'a Value, path: &[&str], values: &mut Vec<&'a Value>) {
+    match (value, path.split_first()) {
+        (Value::Array(items), _) => {
+            for item in items {
+                lookup(item, path, values);
+            }
+        }
+        (_, None) => values.push(value),
+        (Value::Object(map), Some((key, rest))) => {
+            if let Some(child) = map.get(*key) {
+                lookup(child, rest, values);
+            }
+        }
+        _ => (),
+    }
+}
+
+fn find_in_value(value: &Value, field: &str, sightings: &mut Vec<Sighting>) {
+    if let Value::String(text) = value {
+        for ip in get_all_ips(text) {
+            sightings.push(Sighting::new(ip).with("field", field));
+        }
+    }
+}
+
+fn find_in_leaves(value: &Value, path: &str, sightings: &mut Vec<Sighting>) {
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map {
+                let child_path = if path.is_empty() {
+                    key.to_string()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                find_in_leaves(child, &child_path, sightings);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                find_in_leaves(item, path, sightings);
+            }
+        }
+        _ => find_in_value(value, path, sightings),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    const CLOUDTRAIL: &str = r#"{"eventName": "ConsoleLogin", "sourceIPAddress": "198.51.100.7", "userAgent": "aws-internal/3 10.0.0.5"}
+{"eventName": "AssumeRole", "sourceIPAddress": "ec2.amazonaws.com", "requestParameters": {"sourceIdentity": "203.0.113.9"}}
+{"Records": [{"sourceIPAddress": "192.0.2.1"}, {"sourceIPAddress": "2001:db8::1"}]}
+"#;
+
+    fn found(sightings: &[Sighting]) -> Vec<(String, String)> {
+        sightings
+            .iter()
+            .map(|s| (s.ip.to_string(), s.context["field"].clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_json_fields() {
+        let fields = vec![
+            String::from("sourceIPAddress"),
+            String::from("Records.sourceIPAddress"),
+        ];
+        let sightings = parse_json_reader(CLOUDTRAIL.as_bytes(), &fields).unwrap();
+        assert_eq!(
+            vec![
+                (
+                    String::from("198.51.100.7"),
+                    String::from("sourceIPAddress")
+                ),
+                (
+                    String::from("192.0.2.1"),
+                    String::from("Records.sourceIPAddress")
+                ),
+                (
+                    String::from("2001:db8::1"),
+                    String::from("Records.sourceIPAddress")
+                ),
+            ],
+            found(&sightings)
+        );
+    }
+
+    #[test]
+    fn test_parse_json_nested_field() {
+        let input = r#"{"client": {"ipAddress": "8.8.8.8", "device": "10.1.1.1"}}"#;
+        let sightings =
+            parse_json_reader(input.as_bytes(), &[String::from("client.ipAddress")]).unwrap();
+        assert_eq!(
+            vec![(String::from("8.8.8.8"), String::from("client.ipAddress"))],
+            found(&sightings)
+        );
+    }
+
+    #[test]
+    fn test_parse_json_all_fields() {
+        let sightings = parse_json_reader(CLOUDTRAIL.as_bytes(), &[]).unwrap();
+        let fields: Vec<String> = found(&sightings).into_iter().map(|(_, f)| f).collect();
+        assert_eq!(
+            vec![
+                "sourceIPAddress",
+                "userAgent",
+                "requestParameters.sourceIdentity",
+                "Records.sourceIPAddress",
+                "Records.sourceIPAddress",
+            ],
+            fields
+        );
+    }
+
+    #[test]
+    fn test_parse_json_invalid() {
+        let input = "{\"a\": \"1.1.1.1\"}\n{not json\n{\"b\": \"2.2.2.2\", \"c\": \"3.3\n\
+            {\"d\": \"4.4.4.4\",\n{\"e\": \"5.5.5.5\"}\n{\"f\": \"6.6.6.6\"";
+        let sightings = parse_json_reader(input.as_bytes(), &[]).unwrap();
+        let fields: Vec<String> = found(&sightings).into_iter().map(|(_, f)| f).collect();
+        assert_eq!(vec!["a", "e"], fields);
+    }
+
+    #[test]
+    fn test_parse_json_multiline() {
+        let input = "{\n  \"Records\": [\n    {\"sourceIPAddress\": \"192.0.2.1\"},\n    \
+            {\"sourceIPAddress\": \"[ 192.0.2.2 }\"}\n  ]\n}\n{\"ip\": \"192.0.2.3\"} {\"ip\": \"192.0.2.4\"}\n";
+        let sightings = parse_json_reader(input.as_bytes(), &[]).unwrap();
+        assert_eq!(4, sightings.len());
+    }
+
+    #[test]
+    fn test_parse_json_gzip_file() {
+        let mut file = NamedTempFile::new().unwrap();
+        let mut encoder =
+            flate2::write::GzEncoder::new(file.as_file_mut(), flate2::Compression::default());
+        encoder.write_all(CLOUDTRAIL.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        let sightings = parse_json_file(file.path(), &[String::from("sourceIPAddress")]).unwrap();
+        assert_eq!(1, sightings.len());
+    }
+}
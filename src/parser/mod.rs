@@ -1,15 +1,151 @@
+pub mod access_log;
 pub mod compressed;
+pub mod delimited;
+pub mod email;
+pub mod evtx;
+pub mod json;
+pub mod pcap;
 pub mod plain;
+pub mod syslog;
+pub mod zeek;
 
-use anyhow::Result;
-use std::io::{Read, Seek};
+use crate::resolver::dns::DnsResolver;
+use crate::util::hostname::get_all_hostnames;
+use crate::util::scanner::Endpoint;
+use crate::util::{get_all_endpoints_with, get_all_ips, Ipv4Strictness};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, FixedOffset, SecondsFormat, TimeZone, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufReader, Read};
+use std::net::IpAddr;
 use std::path::Path;
 
+/// An address found in a source, with details of where it was found, such
+/// as the field it was read from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sighting {
+    pub ip: IpAddr,
+    pub context: BTreeMap<String, String>,
+}
+
+impl Sighting {
+    pub fn new(ip: IpAddr) -> Sighting {
+        Sighting {
+            ip,
+            context: BTreeMap::new(),
+        }
+    }
+
+    /// Add a context value, such as the field the address was read from
+    pub fn with(mut self, key: &str, value: impl Into<String>) -> Sighting {
+        self.context.insert(key.to_string(), value.into());
+        self
+    }
+}
+
+impl From<Endpoint> for Sighting {
+    /// A sighting with the `port` and `zone` of the endpoint as context
+    fn from(endpoint: Endpoint) -> Sighting {
+        let mut sighting = Sighting::new(endpoint.ip);
+        if let Some(port) = endpoint.port {
+            sighting = sighting.with("port", port.to_string());
+        }
+        if let Some(zone) = endpoint.zone {
+            sighting = sighting.with("zone", zone);
+        }
+        sighting
+    }
+}
+
+/// How the contents of a source are interpreted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InputFormat {
+    /// Scan the whole text for addresses
+    #[default]
+    Text,
+    /// JSON lines or concatenated JSON documents
+    Json,
+    /// CSV, TSV or other delimited rows
+    Delimited,
+    /// Apache or Nginx access log lines
+    AccessLog,
+    /// RFC 3164 or RFC 5424 syslog lines
+    Syslog,
+    /// Zeek TSV or JSON logs
+    Zeek,
+    /// libpcap or pcapng packet captures
+    Capture,
+    /// Windows event logs
+    Evtx,
+    /// Email messages or mbox files
+    Email,
+}
+
+/// Whether the first row of delimited input is a header
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HeaderMode {
+    /// A header if none of its cells hold an address
+    #[default]
+    Detect,
+    Present,
+    Absent,
+}
+
+/// Settings for extracting addresses from structured sources
+#[derive(Clone, Debug, Default)]
+pub struct ParseOptions {
+    pub format: InputFormat,
+    /// Fields to read addresses from. All fields are read when empty.
+    pub fields: Vec<String>,
+    /// Delimiter of delimited input. Defaults to a tab for `.tsv` files and
+    /// a comma otherwise.
+    pub delimiter: Option<u8>,
+    pub header: HeaderMode,
+    /// Format of access log lines. Defaults to the combined format.
+    pub log_format: Option<String>,
+    /// Record the port and IPv6 zone written with each address of text
+    /// input, such as `1.2.3.4:8080` or `[fe80::1%eth0]:22`
+    pub ports: bool,
+    /// How strictly IPv4 addresses in text must be separated from the
+    /// surrounding characters
+    pub strictness: Ipv4Strictness,
+    /// Resolver for the hostnames and URLs in the input, whose addresses
+    /// record the name they were resolved from as `resolved_from` context.
+    /// Hostnames are not extracted when unset.
+    pub hostnames: Option<DnsResolver>,
+}
+
+impl ParseOptions {
+    /// Sightings of the addresses found in text, with their port and zone
+    /// as context when ports are recorded
+    pub fn text_sightings(&self, endpoints: Vec<Endpoint>) -> Vec<Sighting> {
+        endpoints
+            .into_iter()
+            .map(|endpoint| match self.ports {
+                true => Sighting::from(endpoint),
+                false => Sighting::new(endpoint.ip),
+            })
+            .collect()
+    }
+
+    /// The compiled access log format
+    pub fn access_log_format(&self) -> Result<access_log::LogFormat> {
+        self.log_format
+            .as_deref()
+            .unwrap_or("combined")
+            .parse()
+            .map_err(|e| anyhow!("{}", e))
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum SourceFileType {
     NotAFile,
     Plain,
     Gzip,
+    Pcap,
+    Pcapng,
+    Evtx,
 }
 
 pub fn determine_file_type(file_path: &Path) -> Result<SourceFileType> {
@@ -17,23 +153,205 @@ pub fn determine_file_type(file_path: &Path) -> Result<SourceFileType> {
         return Ok(SourceFileType::NotAFile);
     }
 
-    let mut file = std::fs::File::open(file_path)?;
+    // Pipes and devices can only be read once, so they are not sniffed
+    if !file_path.metadata()?.is_file() {
+        return Ok(SourceFileType::Plain);
+    }
+
+    let file = std::fs::File::open(file_path)?;
     // In the future we may need to extend this in order to handle
     // new file formats that we have support for.
-    let mut header = [0u8; 3];
-    file.read_exact(&mut header)?;
-    file.seek(std::io::SeekFrom::Start(0))?;
+    let mut header = Vec::with_capacity(8);
+    file.take(8).read_to_end(&mut header)?;
 
-    if header == [0x1f, 0x8b, 0x08] {
+    if header.starts_with(&[0x1f, 0x8b, 0x08]) {
         // If the header matches the magic number, it's a gzip file
         // Source: https://www.garykessler.net/library/file_sigs.html
         Ok(SourceFileType::Gzip)
+    } else if pcap::PCAP_MAGIC
+        .iter()
+        .any(|magic| header.starts_with(magic))
+    {
+        Ok(SourceFileType::Pcap)
+    } else if header.starts_with(&pcap::PCAPNG_MAGIC) {
+        Ok(SourceFileType::Pcapng)
+    } else if header == evtx::MAGIC {
+        Ok(SourceFileType::Evtx)
     } else {
         // Otherwise, we will treat it like a plain text file
         Ok(SourceFileType::Plain)
     }
 }
 
+/// Extract the addresses from a file based on its detected type. A path
+/// that is not a file is scanned as text.
+pub fn parse_file(file_path: &Path) -> Result<Vec<IpAddr>> {
+    match determine_file_type(file_path)? {
+        SourceFileType::Plain => plain::parse_text_file(file_path),
+        SourceFileType::Gzip => compressed::parse_gzip_file(file_path),
+        SourceFileType::Pcap | SourceFileType::Pcapng => Ok(pcap::parse_capture_file(file_path)?
+            .into_iter()
+            .map(|sighting| sighting.ip)
+            .collect()),
+        SourceFileType::Evtx => Ok(evtx::parse_evtx_file(file_path, &[])?
+            .into_iter()
+            .map(|sighting| sighting.ip)
+            .collect()),
+        SourceFileType::NotAFile => Ok(get_all_ips(&file_path.to_string_lossy())),
+    }
+}
+
+/// Extract the addresses from a text file, which may be gzipped, with the
+/// port and IPv6 zone written alongside each. A path that is not a file is
+/// scanned as text.
+pub fn parse_file_endpoints(file_path: &Path, strictness: Ipv4Strictness) -> Result<Vec<Endpoint>> {
+    match determine_file_type(file_path)? {
+        SourceFileType::Plain => plain::parse_text_file_endpoints(file_path, strictness),
+        SourceFileType::Gzip => {
+            let mut buffer = vec![];
+            open_reader(file_path)?.read_to_end(&mut buffer)?;
+            Ok(get_all_endpoints_with(&buffer, strictness))
+        }
+        SourceFileType::NotAFile => Ok(get_all_endpoints_with(
+            file_path.to_string_lossy().as_bytes(),
+            strictness,
+        )),
+        _ => Ok(parse_file(file_path)?
+            .into_iter()
+            .map(|ip| Endpoint {
+                ip,
+                port: None,
+                zone: None,
+            })
+            .collect()),
+    }
+}
+
+/// The distinct hostnames in the text of a file, which may be gzipped.
+/// Packet captures and event logs are not searched. A path that is not a
+/// file is searched as text.
+pub fn parse_file_hostnames(file_path: &Path) -> Result<Vec<String>> {
+    match determine_file_type(file_path)? {
+        SourceFileType::NotAFile => Ok(get_all_hostnames(&file_path.to_string_lossy())),
+        SourceFileType::Plain | SourceFileType::Gzip => {
+            let mut buffer = vec![];
+            open_reader(file_path)?.read_to_end(&mut buffer)?;
+            Ok(get_all_hostnames(&String::from_utf8_lossy(&buffer)))
+        }
+        SourceFileType::Pcap | SourceFileType::Pcapng | SourceFileType::Evtx => Ok(vec![]),
+    }
+}
+
+/// Open a file for reading, decompressing it if it is gzipped
+pub fn open_reader(file_path: &Path) -> Result<Box<dyn Read + Send>> {
+    let file = std::fs::File::open(file_path)?;
+    match determine_file_type(file_path)? {
+        SourceFileType::Gzip => Ok(Box::new(flate2::read::GzDecoder::new(BufReader::new(file)))),
+        _ => Ok(Box::new(BufReader::new(file))),
+    }
+}
+
+/// The input format of a file with a recognised header, such as a Zeek log,
+/// a packet capture or a Windows event log, or `Text` otherwise. Gzipped files are sniffed
+/// after decompression.
+pub fn detect_input_format(file_path: &Path) -> Result<InputFormat> {
+    if determine_file_type(file_path)? == SourceFileType::NotAFile
+        || !file_path.metadata()?.is_file()
+    {
+        return Ok(InputFormat::Text);
+    }
+    let mut header = Vec::with_capacity(zeek::HEADER.len());
+    open_reader(file_path)?
+        .take(zeek::HEADER.len() as u64)
+        .read_to_end(&mut header)?;
+    let magic = header.get(..4).unwrap_or_default();
+    if header == zeek::HEADER {
+        Ok(InputFormat::Zeek)
+    } else if magic == pcap::PCAPNG_MAGIC || pcap::PCAP_MAGIC.iter().any(|m| magic == m) {
+        Ok(InputFormat::Capture)
+    } else if header.starts_with(evtx::MAGIC) {
+        Ok(InputFormat::Evtx)
+    } else {
+        Ok(InputFormat::Text)
+    }
+}
+
+/// Extract the addresses from a file in the given input format. Text files
+/// with a recognised header are read in their detected format.
+pub fn parse_file_with(file_path: &Path, options: &ParseOptions) -> Result<Vec<Sighting>> {
+    match options.format {
+        InputFormat::Text => match detect_input_format(file_path)? {
+            InputFormat::Text => {
+                Ok(options.text_sightings(parse_file_endpoints(file_path, options.strictness)?))
+            }
+            format => parse_file_with(
+                file_path,
+                &ParseOptions {
+                    format,
+                    ..options.clone()
+                },
+            ),
+        },
+        InputFormat::Json => json::parse_json_file(file_path, &options.fields),
+        InputFormat::Delimited => {
+            let name = file_path.to_string_lossy();
+            let is_tsv = name.ends_with(".tsv") || name.ends_with(".tsv.gz");
+            let delimiter = options
+                .delimiter
+                .unwrap_or(if is_tsv { b'\t' } else { b',' });
+            delimited::parse_delimited_file(file_path, delimiter, options.header, &options.fields)
+        }
+        InputFormat::AccessLog => {
+            access_log::parse_access_log_file(file_path, &options.access_log_format()?)
+        }
+        InputFormat::Syslog => syslog::parse_syslog_file(file_path, options.strictness),
+        InputFormat::Zeek => zeek::parse_zeek_file(file_path, &options.fields),
+        InputFormat::Capture => pcap::parse_capture_file(file_path),
+        InputFormat::Evtx => evtx::parse_evtx_file(file_path, &options.fields),
+        InputFormat::Email => email::parse_email_file(file_path),
+    }
+}
+
+/// Add the earliest and latest `timestamp` context of each address to its
+/// sightings as `first_seen` and `last_seen`, widening any range a source
+/// already recorded, such as the packet times of a capture
+pub fn add_seen_range(sightings: &mut [Sighting]) {
+    let mut seen: HashMap<IpAddr, (DateTime<FixedOffset>, DateTime<FixedOffset>)> = HashMap::new();
+    for sighting in sightings.iter() {
+        for key in ["timestamp", "first_seen", "last_seen"] {
+            let timestamp = match sighting
+                .context
+                .get(key)
+                .map(|t| DateTime::parse_from_rfc3339(t))
+            {
+                Some(Ok(timestamp)) => timestamp,
+                _ => continue,
+            };
+            let range = seen.entry(sighting.ip).or_insert((timestamp, timestamp));
+            range.0 = range.0.min(timestamp);
+            range.1 = range.1.max(timestamp);
+        }
+    }
+    for sighting in sightings.iter_mut() {
+        if let Some((first, last)) = seen.get(&sighting.ip) {
+            sighting
+                .context
+                .insert(String::from("first_seen"), utc_timestamp(first));
+            sighting
+                .context
+                .insert(String::from("last_seen"), utc_timestamp(last));
+        }
+    }
+}
+
+/// A timestamp as UTC RFC 3339 with whole seconds, the format of `timestamp`
+/// context
+pub(crate) fn utc_timestamp<Tz: TimeZone>(timestamp: &DateTime<Tz>) -> String {
+    timestamp
+        .with_timezone(&Utc)
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -53,6 +371,22 @@ mod tests {
         assert_eq!(SourceFileType::NotAFile, actual);
     }
 
+    #[test]
+    fn test_determine_file_type_short() {
+        let mut short_file = NamedTempFile::new().unwrap();
+        short_file.write_all(b"x").unwrap();
+
+        let actual = determine_file_type(short_file.path());
+        assert_eq!(SourceFileType::Plain, actual.unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_determine_file_type_device() {
+        let actual = determine_file_type(Path::new("/dev/null"));
+        assert_eq!(SourceFileType::Plain, actual.unwrap());
+    }
+
     #[test]
     fn test_determine_file_type_plain() {
         // Setup plain text file
@@ -74,4 +408,90 @@ mod tests {
         let actual = determine_file_type(Path::new(gzip_file.path()));
         assert_eq!(SourceFileType::Gzip, actual.unwrap());
     }
+
+    #[test]
+    fn test_detect_input_format() {
+        let mut zeek_file = NamedTempFile::new().unwrap();
+        flate2::write::GzEncoder::new(zeek_file.as_file_mut(), flate2::Compression::default())
+            .write_all(b"#separator \\x09\n#fields\tts\tid.orig_h\n1.0\t10.0.0.1\n")
+            .unwrap();
+        let mut plain_text_file = NamedTempFile::new().unwrap();
+        plain_text_file.write_all(b"#sep").unwrap();
+
+        assert_eq!(
+            InputFormat::Zeek,
+            detect_input_format(zeek_file.path()).unwrap()
+        );
+        assert_eq!(
+            InputFormat::Text,
+            detect_input_format(plain_text_file.path()).unwrap()
+        );
+        let sightings = parse_file_with(zeek_file.path(), &ParseOptions::default()).unwrap();
+        assert_eq!("originator", sightings[0].context["direction"]);
+    }
+
+    #[test]
+    fn test_determine_file_type_pcap() {
+        let mut pcap_file = NamedTempFile::new().unwrap();
+        pcap_file
+            .write_all(&[0xd4, 0xc3, 0xb2, 0xa1, 2, 0])
+            .unwrap();
+        let mut pcapng_file = NamedTempFile::new().unwrap();
+        pcapng_file.write_all(&pcap::PCAPNG_MAGIC).unwrap();
+
+        assert_eq!(
+            SourceFileType::Pcap,
+            determine_file_type(pcap_file.path()).unwrap()
+        );
+        assert_eq!(
+            SourceFileType::Pcapng,
+            determine_file_type(pcapng_file.path()).unwrap()
+        );
+        assert_eq!(
+            InputFormat::Capture,
+            detect_input_format(pcap_file.path()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_determine_file_type_evtx() {
+        let mut evtx_file = NamedTempFile::new().unwrap();
+        evtx_file.write_all(b"ElfFile\x00\x00\x00").unwrap();
+
+        assert_eq!(
+            SourceFileType::Evtx,
+            determine_file_type(evtx_file.path()).unwrap()
+        );
+        assert_eq!(
+            InputFormat::Evtx,
+            detect_input_format(evtx_file.path()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_add_seen_range() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let mut sightings = vec![
+            Sighting::new(ip("8.8.8.8")).with("timestamp", "2023-04-01T10:00:00Z"),
+            Sighting::new(ip("1.1.1.1")),
+            Sighting::new(ip("8.8.8.8")).with("timestamp", "2023-04-01T09:00:00-02:00"),
+            Sighting::new(ip("8.8.8.8")).with("timestamp", "2023-03-31T23:00:00Z"),
+        ];
+        add_seen_range(&mut sightings);
+        for sighting in [&sightings[0], &sightings[2], &sightings[3]] {
+            assert_eq!("2023-03-31T23:00:00Z", sighting.context["first_seen"]);
+            assert_eq!("2023-04-01T11:00:00Z", sighting.context["last_seen"]);
+        }
+        assert!(sightings[1].context.is_empty());
+
+        let mut sightings = vec![
+            Sighting::new(ip("8.8.8.8"))
+                .with("first_seen", "2023-04-01T08:00:00Z")
+                .with("last_seen", "2023-04-01T09:00:00Z"),
+            Sighting::new(ip("8.8.8.8")).with("timestamp", "2023-04-01T10:00:00Z"),
+        ];
+        add_seen_range(&mut sightings);
+        assert_eq!("2023-04-01T08:00:00Z", sightings[1].context["first_seen"]);
+        assert_eq!("2023-04-01T10:00:00Z", sightings[0].context["last_seen"]);
+    }
 }
@@ -0,0 +1,558 @@
+use crate::parser::{open_reader, utc_timestamp, Sighting};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+
+/// The magic numbers of libpcap files, with microsecond and nanosecond
+/// timestamps, in both byte orders
+pub const PCAP_MAGIC: [[u8; 4]; 4] = [
+    [0xa1, 0xb2, 0xc3, 0xd4],
+    [0xd4, 0xc3, 0xb2, 0xa1],
+    [0xa1, 0xb2, 0x3c, 0x4d],
+    [0x4d, 0x3c, 0xb2, 0xa1],
+];
+/// The block type of a pcapng section header, which starts the file
+pub const PCAPNG_MAGIC: [u8; 4] = [0x0a, 0x0d, 0x0d, 0x0a];
+
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+
+/// The largest packet libpcap captures, so that a corrupt length does not
+/// allocate gigabytes
+const MAX_PACKET_LENGTH: usize = 262_144;
+/// The largest pcapng block read, as in Wireshark
+const MAX_BLOCK_LENGTH: usize = 16 * 1024 * 1024;
+
+// Link types, from https://www.tcpdump.org/linktypes.html
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LOOP: u32 = 108;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+#[derive(Clone, Copy)]
+enum Endian {
+    Little,
+    Big,
+}
+
+impl Endian {
+    fn u16(self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        match self {
+            Endian::Little => u16::from_le_bytes(bytes),
+            Endian::Big => u16::from_be_bytes(bytes),
+        }
+    }
+
+    fn u32(self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        match self {
+            Endian::Little => u32::from_le_bytes(bytes),
+            Endian::Big => u32::from_be_bytes(bytes),
+        }
+    }
+}
+
+/// Units of a packet timestamp
+#[derive(Clone, Copy)]
+enum Resolution {
+    /// 10^-n seconds
+    Decimal(u32),
+    /// 2^-n seconds
+    Binary(u32),
+}
+
+impl Resolution {
+    fn timestamp(self, units: u64) -> Option<DateTime<Utc>> {
+        let (seconds, nanos) = match self {
+            Resolution::Decimal(n) => {
+                let per_second = 10u64.checked_pow(n)?;
+                let fraction = (units % per_second) as u128;
+                (
+                    units / per_second,
+                    fraction * 1_000_000_000 / per_second as u128,
+                )
+            }
+            Resolution::Binary(n) if n < 64 => {
+                let fraction = (units & ((1 << n) - 1)) as u128;
+                (units >> n, (fraction * 1_000_000_000) >> n)
+            }
+            Resolution::Binary(_) => return None,
+        };
+        DateTime::from_timestamp(i64::try_from(seconds).ok()?, nanos as u32)
+    }
+}
+
+/// Packet counts and times of an address
+#[derive(Default)]
+struct Tally {
+    source: u64,
+    destination: u64,
+    first: Option<DateTime<Utc>>,
+    last: Option<DateTime<Utc>>,
+}
+
+/// Tallies of each address, in order of first appearance
+#[derive(Default)]
+struct Tallies {
+    order: Vec<IpAddr>,
+    by_ip: HashMap<IpAddr, Tally>,
+}
+
+impl Tallies {
+    fn add(&mut self, linktype: u32, data: &[u8], timestamp: Option<DateTime<Utc>>) {
+        let (source, destination) = match packet_addresses(linktype, data) {
+            Some(addresses) => addresses,
+            None => return,
+        };
+        for (ip, is_source) in [(source, true), (destination, false)] {
+            let order = &mut self.order;
+            let tally = self.by_ip.entry(ip).or_insert_with(|| {
+                order.push(ip);
+                Tally::default()
+            });
+            if is_source {
+                tally.source += 1;
+            } else {
+                tally.destination += 1;
+            }
+            if let Some(timestamp) = timestamp {
+                tally.first = Some(tally.first.map_or(timestamp, |t| t.min(timestamp)));
+                tally.last = Some(tally.last.map_or(timestamp, |t| t.max(timestamp)));
+            }
+        }
+    }
+
+    fn into_sightings(mut self) -> Vec<Sighting> {
+        self.order
+            .into_iter()
+            .filter_map(|ip| {
+                let tally = self.by_ip.remove(&ip)?;
+                let mut sighting = Sighting::new(ip)
+                    .with("packets", (tally.source + tally.destination).to_string())
+                    .with("source_packets", tally.source.to_string())
+                    .with("destination_packets", tally.destination.to_string());
+                if let (Some(first), Some(last)) = (tally.first, tally.last) {
+                    sighting = sighting
+                        .with("first_seen", utc_timestamp(&first))
+                        .with("last_seen", utc_timestamp(&last));
+                }
+                Some(sighting)
+            })
+            .collect()
+    }
+}
+
+/// The source and destination of the IP header at the start of the data
+fn ip_addresses(data: &[u8]) -> Option<(IpAddr, IpAddr)> {
+    match data.first()? >> 4 {
+        4 if data.len() >= 20 => {
+            let source: [u8; 4] = data[12..16].try_into().ok()?;
+            let destination: [u8; 4] = data[16..20].try_into().ok()?;
+            Some((
+                IpAddr::V4(Ipv4Addr::from(source)),
+                IpAddr::V4(Ipv4Addr::from(destination)),
+            ))
+        }
+        6 if data.len() >= 40 => {
+            let source: [u8; 16] = data[8..24].try_into().ok()?;
+            let destination: [u8; 16] = data[24..40].try_into().ok()?;
+            Some((
+                IpAddr::V6(Ipv6Addr::from(source)),
+                IpAddr::V6(Ipv6Addr::from(destination)),
+            ))
+        }
+        _ => None,
+    }
+}
+
+fn by_ethertype(ethertype: u16, payload: &[u8]) -> Option<(IpAddr, IpAddr)> {
+    match ethertype {
+        0x0800 | 0x86dd => ip_addresses(payload),
+        _ => None,
+    }
+}
+
+/// The source and destination of the outermost IP header of a packet
+fn packet_addresses(linktype: u32, data: &[u8]) -> Option<(IpAddr, IpAddr)> {
+    match linktype {
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype = Endian::Big.u16(data.get(offset..offset + 2)?);
+            // Skip 802.1Q and 802.1ad VLAN tags
+            while matches!(ethertype, 0x8100 | 0x88a8 | 0x9100) {
+                offset += 4;
+                ethertype = Endian::Big.u16(data.get(offset..offset + 2)?);
+            }
+            by_ethertype(ethertype, data.get(offset + 2..)?)
+        }
+        LINKTYPE_NULL | LINKTYPE_LOOP => ip_addresses(data.get(4..)?),
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => ip_addresses(data),
+        LINKTYPE_LINUX_SLL => by_ethertype(Endian::Big.u16(data.get(14..16)?), data.get(16..)?),
+        LINKTYPE_LINUX_SLL2 => by_ethertype(Endian::Big.u16(data.get(0..2)?), data.get(20..)?),
+        _ => None,
+    }
+}
+
+/// Fill the buffer, returning false at the end of the data. A truncated
+/// capture, such as one from an interrupted tcpdump, ends at its last
+/// whole packet.
+fn read_block<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<bool> {
+    match reader.read_exact(buffer) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(anyhow!("Unable to read packet capture: {}", e)),
+    }
+}
+
+fn read_pcap<R: Read>(mut reader: R, magic: [u8; 4], tallies: &mut Tallies) -> Result<()> {
+    // Microsecond or nanosecond timestamps
+    let (endian, digits) = match magic {
+        [0xa1, 0xb2, 0xc3, 0xd4] => (Endian::Big, 6),
+        [0xd4, 0xc3, 0xb2, 0xa1] => (Endian::Little, 6),
+        [0xa1, 0xb2, 0x3c, 0x4d] => (Endian::Big, 9),
+        [0x4d, 0x3c, 0xb2, 0xa1] => (Endian::Little, 9),
+        _ => return Err(anyhow!("Not a packet capture")),
+    };
+    let mut header = [0; 20];
+    if !read_block(&mut reader, &mut header)? {
+        return Ok(());
+    }
+    // The upper bits may hold the FCS length
+    let linktype = endian.u32(&header[16..20]) & 0x0fff_ffff;
+
+    let mut record = [0; 16];
+    let mut data = vec![];
+    while read_block(&mut reader, &mut record)? {
+        let seconds = endian.u32(&record[0..4]) as u64;
+        let fraction = endian.u32(&record[4..8]) as u64;
+        let units = seconds * 10u64.pow(digits) + fraction;
+        let captured = endian.u32(&record[8..12]) as usize;
+        if captured > MAX_PACKET_LENGTH {
+            return Err(anyhow!("Invalid packet length {}", captured));
+        }
+        data.resize(captured, 0);
+        if !read_block(&mut reader, &mut data)? {
+            break;
+        }
+        tallies.add(
+            linktype,
+            &data,
+            Resolution::Decimal(digits).timestamp(units),
+        );
+    }
+    Ok(())
+}
+
+/// Link type and timestamp resolution of a pcapng interface
+struct Interface {
+    linktype: u32,
+    resolution: Resolution,
+}
+
+fn interface(endian: Endian, body: &[u8]) -> Option<Interface> {
+    let linktype = endian.u16(body.get(0..2)?) as u32;
+    let mut resolution = Resolution::Decimal(6);
+    let mut options = body.get(8..)?;
+    while options.len() >= 4 {
+        let code = endian.u16(&options[0..2]);
+        let length = endian.u16(&options[2..4]) as usize;
+        let value = options.get(4..4 + length)?;
+        match code {
+            0 => break,
+            // if_tsresol
+            9 if length == 1 => {
+                resolution = match value[0] {
+                    n if n & 0x80 == 0 => Resolution::Decimal(n as u32),
+                    n => Resolution::Binary((n & 0x7f) as u32),
+                };
+            }
+            _ => (),
+        }
+        options = options
+            .get(4 + length.div_ceil(4) * 4..)
+            .unwrap_or_default();
+    }
+    Some(Interface {
+        linktype,
+        resolution,
+    })
+}
+
+/// Tally an enhanced or obsolete packet block, which share a layout after
+/// the interface id
+fn add_packet(tallies: &mut Tallies, endian: Endian, interface: Option<&Interface>, block: &[u8]) {
+    let captured = endian.u32(&block[12..16]) as usize;
+    if let (Some(interface), Some(data)) = (interface, block.get(20..20 + captured)) {
+        let units = ((endian.u32(&block[4..8]) as u64) << 32) | endian.u32(&block[8..12]) as u64;
+        tallies.add(
+            interface.linktype,
+            data,
+            interface.resolution.timestamp(units),
+        );
+    }
+}
+
+fn read_pcapng<R: Read>(mut reader: R, tallies: &mut Tallies) -> Result<()> {
+    let mut endian = Endian::Little;
+    let mut interfaces: Vec<Interface> = vec![];
+    // The block type and length, starting with the section header type
+    // already read as the magic number
+    let mut header = [0; 8];
+    header[..4].copy_from_slice(&PCAPNG_MAGIC);
+    if !read_block(&mut reader, &mut header[4..])? {
+        return Ok(());
+    }
+    let mut body = vec![];
+    loop {
+        let mut read = header.len();
+        if header[..4] == PCAPNG_MAGIC {
+            // A section header sets the byte order of the blocks after it
+            let mut byte_order = [0; 4];
+            if !read_block(&mut reader, &mut byte_order)? {
+                break;
+            }
+            endian = match Endian::Little.u32(&byte_order) {
+                BYTE_ORDER_MAGIC => Endian::Little,
+                _ if Endian::Big.u32(&byte_order) == BYTE_ORDER_MAGIC => Endian::Big,
+                _ => return Err(anyhow!("Invalid pcapng byte order")),
+            };
+            interfaces.clear();
+            read += byte_order.len();
+        }
+        let length = endian.u32(&header[4..8]) as usize;
+        if length < read + 4 || !length.is_multiple_of(4) || length > MAX_BLOCK_LENGTH {
+            return Err(anyhow!("Invalid pcapng block length {}", length));
+        }
+        body.resize(length - read, 0);
+        if !read_block(&mut reader, &mut body)? {
+            break;
+        }
+        // Without the trailing copy of the length
+        let block = &body[..body.len() - 4];
+        match endian.u32(&header[..4]) {
+            1 => interfaces.extend(interface(endian, block)),
+            // Obsolete packet, with a 16-bit interface id
+            2 if block.len() >= 20 => {
+                let interface = interfaces.get(endian.u16(&block[0..2]) as usize);
+                add_packet(tallies, endian, interface, block);
+            }
+            // Simple packet, which has no timestamp
+            3 if block.len() >= 4 => {
+                if let Some(interface) = interfaces.first() {
+                    tallies.add(interface.linktype, &block[4..], None);
+                }
+            }
+            // Enhanced packet
+            6 if block.len() >= 20 => {
+                let interface = interfaces.get(endian.u32(&block[0..4]) as usize);
+                add_packet(tallies, endian, interface, block);
+            }
+            _ => (),
+        }
+        if !read_block(&mut reader, &mut header)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Extract the source and destination addresses of the packets in a
+/// libpcap or pcapng file, which may be gzipped
+pub fn parse_capture_file(file_path: &Path) -> Result<Vec<Sighting>> {
+    parse_capture_reader(open_reader(file_path)?)
+}
+
+/// Extract the source and destination addresses of the IP header of each
+/// packet in a libpcap or pcapng capture.
+///
+/// Each address is sighted once, with its `packets`, `source_packets` and
+/// `destination_packets` counts and the `first_seen` and `last_seen` times
+/// of its packets as context.
+pub fn parse_capture_reader<R: Read>(mut reader: R) -> Result<Vec<Sighting>> {
+    let mut magic = [0; 4];
+    reader
+        .read_exact(&mut magic)
+        .map_err(|_| anyhow!("Not a packet capture"))?;
+    let mut tallies = Tallies::default();
+    if magic == PCAPNG_MAGIC {
+        read_pcapng(reader, &mut tallies)?;
+    } else {
+        read_pcap(reader, magic, &mut tallies)?;
+    }
+    Ok(tallies.into_sightings())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ipv4_packet(source: [u8; 4], destination: [u8; 4]) -> Vec<u8> {
+        let mut packet = vec![0x45, 0, 0, 20, 0, 0, 0, 0, 64, 6, 0, 0];
+        packet.extend(source);
+        packet.extend(destination);
+        packet
+    }
+
+    fn ethernet(ethertype: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0; 12];
+        frame.extend(ethertype);
+        frame.extend(payload);
+        frame
+    }
+
+    fn pcap(linktype: u32, packets: &[(u32, u32, Vec<u8>)]) -> Vec<u8> {
+        let mut file = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        file.extend([0; 8]);
+        file.extend(65535u32.to_le_bytes());
+        file.extend(linktype.to_le_bytes());
+        for (seconds, micros, data) in packets {
+            file.extend(seconds.to_le_bytes());
+            file.extend(micros.to_le_bytes());
+            file.extend((data.len() as u32).to_le_bytes());
+            file.extend((data.len() as u32).to_le_bytes());
+            file.extend(data);
+        }
+        file
+    }
+
+    fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+        let length = (12 + body.len().div_ceil(4) * 4) as u32;
+        let mut block = block_type.to_be_bytes().to_vec();
+        block.extend(length.to_be_bytes());
+        block.extend(body);
+        block.resize(length as usize - 4, 0);
+        block.extend(length.to_be_bytes());
+        block
+    }
+
+    fn context(sighting: &Sighting) -> Vec<(&str, &str)> {
+        sighting
+            .context
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_pcap_ethernet() {
+        let packet = |s, d| ethernet(&[0x08, 0x00], &ipv4_packet(s, d));
+        let vlan = ethernet(
+            &[0x81, 0x00, 0x00, 0x0a, 0x08, 0x00],
+            &ipv4_packet([192, 0, 2, 1], [10, 0, 0, 5]),
+        );
+        let file = pcap(
+            LINKTYPE_ETHERNET,
+            &[
+                (1680343200, 500_000, packet([10, 0, 0, 5], [192, 0, 2, 1])),
+                (1680343260, 0, vlan),
+                (1680343300, 0, ethernet(&[0x08, 0x06], &[0; 28])),
+            ],
+        );
+        let sightings = parse_capture_reader(&file[..]).unwrap();
+        assert_eq!(2, sightings.len());
+        assert_eq!("10.0.0.5", sightings[0].ip.to_string());
+        assert_eq!(
+            vec![
+                ("destination_packets", "1"),
+                ("first_seen", "2023-04-01T10:00:00Z"),
+                ("last_seen", "2023-04-01T10:01:00Z"),
+                ("packets", "2"),
+                ("source_packets", "1"),
+            ],
+            context(&sightings[0])
+        );
+    }
+
+    #[test]
+    fn test_pcap_truncated() {
+        let mut file = pcap(
+            LINKTYPE_RAW,
+            &[
+                (0, 0, ipv4_packet([8, 8, 8, 8], [1, 1, 1, 1])),
+                (0, 0, ipv4_packet([9, 9, 9, 9], [1, 1, 1, 1])),
+            ],
+        );
+        file.truncate(file.len() - 5);
+        let sightings = parse_capture_reader(&file[..]).unwrap();
+        assert_eq!(2, sightings.len());
+    }
+
+    #[test]
+    fn test_pcap_oversized_packet() {
+        let mut file = pcap(LINKTYPE_RAW, &[(0, 0, vec![0x45; 20])]);
+        file[32..36].copy_from_slice(&u32::MAX.to_le_bytes());
+        let error = parse_capture_reader(&file[..]).unwrap_err();
+        assert_eq!("Invalid packet length 4294967295", error.to_string());
+    }
+
+    #[test]
+    fn test_pcapng_oversized_block() {
+        let mut file = block(0x0a0d0d0a, &BYTE_ORDER_MAGIC.to_be_bytes());
+        file.extend(block(6, &[0; 20]));
+        // The length of the enhanced packet block after the section header
+        file[20..24].copy_from_slice(&0xfffffff0u32.to_be_bytes());
+        let error = parse_capture_reader(&file[..]).unwrap_err();
+        assert_eq!("Invalid pcapng block length 4294967280", error.to_string());
+    }
+
+    #[test]
+    fn test_pcapng() {
+        let mut section = BYTE_ORDER_MAGIC.to_be_bytes().to_vec();
+        section.extend([0, 1, 0, 0]);
+        section.extend(u64::MAX.to_be_bytes());
+        // Raw IP with nanosecond timestamps
+        let mut interface = (LINKTYPE_RAW as u16).to_be_bytes().to_vec();
+        interface.extend([0, 0, 0, 0, 0, 0]);
+        interface.extend([0, 9, 0, 1, 9, 0, 0, 0, 0, 0, 0, 0]);
+
+        let mut source = [0; 16];
+        source[0..2].copy_from_slice(&[0x20, 0x01]);
+        source[15] = 1;
+        let mut ipv6 = vec![0x60, 0, 0, 0, 0, 0, 17, 64];
+        ipv6.extend(source);
+        ipv6.extend(Ipv6Addr::LOCALHOST.octets());
+        let units: u64 = 1_680_343_200_000_000_000;
+        let mut enhanced = 0u32.to_be_bytes().to_vec();
+        enhanced.extend(((units >> 32) as u32).to_be_bytes());
+        enhanced.extend((units as u32).to_be_bytes());
+        enhanced.extend((ipv6.len() as u32).to_be_bytes());
+        enhanced.extend((ipv6.len() as u32).to_be_bytes());
+        enhanced.extend(&ipv6);
+
+        let mut simple = 20u32.to_be_bytes().to_vec();
+        simple.extend(ipv4_packet([203, 0, 113, 9], [198, 51, 100, 7]));
+
+        let mut file = block(0x0a0d0d0a, &section);
+        file.extend(block(1, &interface));
+        file.extend(block(6, &enhanced));
+        file.extend(block(3, &simple));
+        file.extend(block(5, &[0; 8]));
+
+        let sightings = parse_capture_reader(&file[..]).unwrap();
+        let ips: Vec<String> = sightings.iter().map(|s| s.ip.to_string()).collect();
+        assert_eq!(vec!["2001::1", "::1", "203.0.113.9", "198.51.100.7"], ips);
+        assert_eq!("2023-04-01T10:00:00Z", sightings[0].context["first_seen"]);
+        assert!(!sightings[2].context.contains_key("first_seen"));
+    }
+
+    #[test]
+    fn test_resolution() {
+        let t = Resolution::Binary(1).timestamp(3).unwrap();
+        assert_eq!(500_000_000, t.timestamp_subsec_nanos());
+        assert_eq!(1, t.timestamp());
+        assert!(Resolution::Decimal(30).timestamp(1).is_none());
+    }
+
+    #[test]
+    fn test_not_a_capture() {
+        assert!(parse_capture_reader(&b"1.1.1.1 2.2.2.2"[..]).is_err());
+        assert!(parse_capture_reader(&b"1."[..]).is_err());
+    }
+}
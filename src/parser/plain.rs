@@ -1,12 +1,45 @@
 use anyhow::Result;
+use memmap2::Mmap;
 use std::fs;
+use std::io::Read;
 use std::net::IpAddr;
 use std::path::Path;
 
-use crate::util::get_all_ips;
+use crate::util::scanner::Endpoint;
+use crate::util::{get_all_endpoints_with, get_all_ips_in_bytes, Ipv4Strictness};
 
+/// Extract the addresses from a plain text file.
+///
+/// Regular files are memory mapped and scanned as bytes, so they are
+/// neither copied nor validated as UTF-8. Pipes, devices and files that
+/// report no size, such as those in /proc, are read into memory instead.
 pub fn parse_text_file(file_path: &Path) -> Result<Vec<IpAddr>> {
-    Ok(get_all_ips(fs::read_to_string(file_path)?.as_str()))
+    scan_text_file(file_path, get_all_ips_in_bytes)
+}
+
+/// Extract the addresses from a plain text file with the port and IPv6 zone
+/// written alongside each
+pub fn parse_text_file_endpoints(
+    file_path: &Path,
+    strictness: Ipv4Strictness,
+) -> Result<Vec<Endpoint>> {
+    scan_text_file(file_path, |data| get_all_endpoints_with(data, strictness))
+}
+
+fn scan_text_file<T>(file_path: &Path, scan: impl Fn(&[u8]) -> Vec<T>) -> Result<Vec<T>> {
+    let mut file = fs::File::open(file_path)?;
+    let metadata = file.metadata()?;
+    if metadata.is_file() && metadata.len() > 0 {
+        // SAFETY: the map is only read while scanning. Truncating the file
+        // from another process during the scan is not supported.
+        if let Ok(map) = unsafe { Mmap::map(&file) } {
+            return Ok(scan(&map));
+        }
+    }
+
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer)?;
+    Ok(scan(&buffer))
 }
 
 #[cfg(test)]
@@ -48,4 +81,79 @@ mod test {
             assert_eq!(expected, actual);
         }
     }
+
+    #[test]
+    fn test_parse_text_file_not_utf8() {
+        let mut sample = NamedTempFile::new().unwrap();
+        sample
+            .write_all(b"\xff\xfe 8.8.8.8 \xc3\x28 2001:db8::1")
+            .unwrap();
+
+        let actual = parse_text_file(sample.path()).unwrap();
+        assert_eq!(
+            vec![
+                IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)),
+                IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+            ],
+            actual
+        );
+    }
+
+    #[test]
+    fn test_parse_text_file_endpoints() {
+        let mut sample = NamedTempFile::new().unwrap();
+        sample
+            .write_all(b"ESTAB 10.0.0.5:52144 [2001:db8::1]:443 fe80::1%eth0")
+            .unwrap();
+
+        let actual: Vec<(Option<u16>, Option<String>)> =
+            parse_text_file_endpoints(sample.path(), Ipv4Strictness::default())
+                .unwrap()
+                .into_iter()
+                .map(|endpoint| (endpoint.port, endpoint.zone))
+                .collect();
+        assert_eq!(
+            vec![
+                (Some(52144), None),
+                (Some(443), None),
+                (None, Some(String::from("eth0"))),
+            ],
+            actual
+        );
+    }
+
+    #[test]
+    fn test_parse_text_file_empty() {
+        let sample = NamedTempFile::new().unwrap();
+        assert!(parse_text_file(sample.path()).unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_parse_text_file_pipe() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fifo = dir.path().join("fifo");
+        let path = std::ffi::CString::new(fifo.as_os_str().as_encoded_bytes()).unwrap();
+        // SAFETY: the path is a valid NUL-terminated string
+        assert_eq!(0, unsafe { libc::mkfifo(path.as_ptr(), 0o600) });
+
+        let writer_path = fifo.clone();
+        let writer = std::thread::spawn(move || {
+            let mut pipe = fs::OpenOptions::new()
+                .write(true)
+                .open(writer_path)
+                .unwrap();
+            pipe.write_all(b"1.1.1.1 9.9.9.9").unwrap();
+        });
+        let actual = parse_text_file(&fifo).unwrap();
+        writer.join().unwrap();
+
+        assert_eq!(
+            vec![
+                IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)),
+                IpAddr::V4(Ipv4Addr::new(9, 9, 9, 9)),
+            ],
+            actual
+        );
+    }
 }
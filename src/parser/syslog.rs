@@ -0,0 +1,259 @@
+use crate::parser::{open_reader, utc_timestamp, Sighting};
+use crate::util::{get_all_ips_in_bytes_with, Ipv4Strictness};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, Utc};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::io::{BufRead, BufReader, Read};
+use std::net::IpAddr;
+use std::path::Path;
+
+lazy_static! {
+    // <PRI>VERSION TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG
+    static ref RFC5424: Regex = Regex::new(
+        r"^<\d{1,3}>\d{1,2} (\S+) (\S+) (\S+) \S+ \S+ (?:-|(?:\[(?:[^\]\\]|\\.)*\])+) ?(.*)$"
+    )
+    .unwrap();
+    // [<PRI>]TIMESTAMP HOSTNAME [TAG[PID]: ]MSG, with a BSD or ISO timestamp.
+    // The tag must be followed by a space so that IP:port is not a tag.
+    static ref RFC3164: Regex = Regex::new(
+        r"^(?:<\d{1,3}>)?([A-Z][a-z]{2} [ \d]\d \d{2}:\d{2}:\d{2}|\d{4}-\d{2}-\d{2}T\S+) (\S+) (?:([^\s:\[]+)(?:\[[^\]]*\])?:(?: |$))?(.*)$"
+    )
+    .unwrap();
+}
+
+/// The header of a syslog line and the message body
+struct Message<'a> {
+    timestamp: Option<String>,
+    hostname: &'a str,
+    app_name: Option<&'a str>,
+    body: &'a str,
+}
+
+fn split_message(line: &str, now: DateTime<Utc>) -> Option<Message<'_>> {
+    let present = |value: &str| value != "-";
+    if let Some(captures) = RFC5424.captures(line) {
+        let field = |i: usize| captures.get(i).map_or("", |m| m.as_str());
+        return Some(Message {
+            timestamp: Some(field(1)).filter(|t| present(t)).map(timestamp_context),
+            hostname: field(2),
+            app_name: Some(field(3)).filter(|a| present(a)),
+            body: field(4),
+        });
+    }
+    let captures = RFC3164.captures(line)?;
+    let timestamp = captures.get(1).map_or("", |m| m.as_str());
+    let timestamp = match DateTime::parse_from_rfc3339(timestamp) {
+        Ok(timestamp) => utc_timestamp(&timestamp),
+        Err(_) => bsd_timestamp(timestamp, now).unwrap_or_else(|| timestamp.to_string()),
+    };
+    let body = captures.get(4).map_or("", |m| m.as_str());
+    // A message that starts with an address, such as "203.0.113.9: blocked",
+    // has no tag
+    let (app_name, body) = match captures.get(3) {
+        Some(tag) if tag.as_str().parse::<IpAddr>().is_ok() => (None, &line[tag.start()..]),
+        tag => (tag.map(|m| m.as_str()), body),
+    };
+    Some(Message {
+        timestamp: Some(timestamp),
+        hostname: captures.get(2).map_or("", |m| m.as_str()),
+        app_name,
+        body,
+    })
+}
+
+fn timestamp_context(timestamp: &str) -> String {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|t| utc_timestamp(&t))
+        .unwrap_or_else(|_| timestamp.to_string())
+}
+
+/// A `Mmm dd hh:mm:ss` timestamp, which has no year or time zone, read as
+/// UTC in the most recent year that does not place it in the future
+fn bsd_timestamp(timestamp: &str, now: DateTime<Utc>) -> Option<String> {
+    let timestamp = timestamp.replace("  ", " ");
+    let in_year = |year: i32| {
+        NaiveDateTime::parse_from_str(&format!("{} {}", year, timestamp), "%Y %b %d %H:%M:%S")
+            .ok()
+            .map(|t| t.and_utc())
+    };
+    let parsed = match in_year(now.year()) {
+        Some(t) if t <= now + Duration::days(1) => Some(t),
+        _ => in_year(now.year() - 1),
+    };
+    parsed.map(|t| utc_timestamp(&t))
+}
+
+/// The addresses in the message body of a syslog line, with the timestamp,
+/// hostname and app name of the header as context. Lines without a header
+/// are scanned whole, without context.
+fn parse_line(line: &str, now: DateTime<Utc>, strictness: Ipv4Strictness) -> Vec<Sighting> {
+    let scan = |text: &str| get_all_ips_in_bytes_with(text.as_bytes(), strictness);
+    let message = match split_message(line, now) {
+        Some(message) => message,
+        None => return scan(line).into_iter().map(Sighting::new).collect(),
+    };
+    scan(message.body)
+        .into_iter()
+        .map(|ip| {
+            let mut sighting = Sighting::new(ip).with("hostname", message.hostname);
+            if let Some(timestamp) = &message.timestamp {
+                sighting = sighting.with("timestamp", timestamp.as_str());
+            }
+            if let Some(app_name) = message.app_name {
+                sighting = sighting.with("app_name", app_name);
+            }
+            sighting
+        })
+        .collect()
+}
+
+/// Extract the addresses from the message bodies of a syslog file, which
+/// may be gzipped
+pub fn parse_syslog_file(file_path: &Path, strictness: Ipv4Strictness) -> Result<Vec<Sighting>> {
+    parse_syslog_reader(open_reader(file_path)?, strictness)
+}
+
+/// Extract the addresses from the message body of each RFC 3164 or RFC 5424
+/// syslog line.
+///
+/// Timestamps are converted to UTC. RFC 3164 timestamps have no time zone
+/// or year, so they are read as UTC in the most recent matching year.
+pub fn parse_syslog_reader<R: Read>(
+    reader: R,
+    strictness: Ipv4Strictness,
+) -> Result<Vec<Sighting>> {
+    let now = Utc::now();
+    let mut reader = BufReader::new(reader);
+    let mut sightings = vec![];
+    let mut line = vec![];
+    loop {
+        line.clear();
+        let read = reader
+            .read_until(b'\n', &mut line)
+            .map_err(|e| anyhow!("Unable to read syslog: {}", e))?;
+        if read == 0 {
+            break;
+        }
+        let text = String::from_utf8_lossy(&line);
+        let text = text.trim_end_matches(['\r', '\n']);
+        sightings.extend(parse_line(text, now, strictness));
+    }
+    Ok(sightings)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 4, 1, 12, 0, 0).unwrap()
+    }
+
+    fn context(sighting: &Sighting) -> Vec<(&str, &str)> {
+        sighting
+            .context
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_rfc3164_auth_log() {
+        let line = "Mar 31 22:14:15 bastion sshd[4121]: Failed password for root from 203.0.113.9 port 52144 ssh2";
+        let sightings = parse_line(line, now(), Ipv4Strictness::default());
+        assert_eq!(1, sightings.len());
+        assert_eq!("203.0.113.9", sightings[0].ip.to_string());
+        assert_eq!(
+            vec![
+                ("app_name", "sshd"),
+                ("hostname", "bastion"),
+                ("timestamp", "2023-03-31T22:14:15Z"),
+            ],
+            context(&sightings[0])
+        );
+    }
+
+    #[test]
+    fn test_rfc3164_hostname_not_extracted() {
+        let line = "<34>Apr  1 09:00:00 10.0.0.1 kernel: DROP SRC=198.51.100.7";
+        let sightings = parse_line(line, now(), Ipv4Strictness::default());
+        assert_eq!(1, sightings.len());
+        assert_eq!("198.51.100.7", sightings[0].ip.to_string());
+        assert_eq!("10.0.0.1", sightings[0].context["hostname"]);
+        assert_eq!("2023-04-01T09:00:00Z", sightings[0].context["timestamp"]);
+    }
+
+    #[test]
+    fn test_rfc3164_previous_year() {
+        let line = "Dec 31 23:59:59 bastion sshd[1]: from 192.0.2.1";
+        let sightings = parse_line(line, now(), Ipv4Strictness::default());
+        assert_eq!("2022-12-31T23:59:59Z", sightings[0].context["timestamp"]);
+    }
+
+    #[test]
+    fn test_rfc3164_iso_timestamp() {
+        let line = "2023-04-01T10:00:00.123+02:00 web01 nginx: upstream 192.0.2.1";
+        let sightings = parse_line(line, now(), Ipv4Strictness::default());
+        assert_eq!("2023-04-01T08:00:00Z", sightings[0].context["timestamp"]);
+        assert_eq!("nginx", sightings[0].context["app_name"]);
+    }
+
+    #[test]
+    fn test_rfc3164_address_not_tag() {
+        let line = "Apr  1 09:00:00 fw 203.0.113.9:443 blocked";
+        let sightings = parse_line(line, now(), Ipv4Strictness::default());
+        assert_eq!(1, sightings.len());
+        assert_eq!("203.0.113.9", sightings[0].ip.to_string());
+        assert!(!sightings[0].context.contains_key("app_name"));
+
+        let line = "Apr  1 09:00:00 fw 203.0.113.9: blocked";
+        let sightings = parse_line(line, now(), Ipv4Strictness::default());
+        assert_eq!(1, sightings.len());
+        assert!(!sightings[0].context.contains_key("app_name"));
+
+        let line = "Apr  1 09:00:00 fw kernel:";
+        assert!(parse_line(line, now(), Ipv4Strictness::default()).is_empty());
+    }
+
+    #[test]
+    fn test_rfc5424() {
+        let line = r#"<165>1 2023-04-01T10:00:00Z fw01 filterlog 123 ID47 [origin ip="10.9.9.9"] block 198.51.100.7"#;
+        let sightings = parse_line(line, now(), Ipv4Strictness::default());
+        assert_eq!(1, sightings.len());
+        assert_eq!(
+            vec![
+                ("app_name", "filterlog"),
+                ("hostname", "fw01"),
+                ("timestamp", "2023-04-01T10:00:00Z"),
+            ],
+            context(&sightings[0])
+        );
+
+        let line = "<165>1 - fw01 - - - - 198.51.100.7";
+        let sightings = parse_line(line, now(), Ipv4Strictness::default());
+        assert_eq!(vec![("hostname", "fw01")], context(&sightings[0]));
+    }
+
+    #[test]
+    fn test_line_without_header() {
+        let sightings = parse_syslog_reader(
+            "connection from 8.8.8.8\n".as_bytes(),
+            Ipv4Strictness::default(),
+        )
+        .unwrap();
+        assert_eq!(1, sightings.len());
+        assert!(sightings[0].context.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_utf8_line() {
+        let input = b"Apr  1 09:00:00 fw kernel: 192.0.2.1\n\
+            Apr  1 09:00:01 fw kernel: \xff\xfe 192.0.2.2\n\
+            Apr  1 09:00:02 fw kernel: 192.0.2.3\n";
+        let sightings = parse_syslog_reader(&input[..], Ipv4Strictness::default()).unwrap();
+        let ips: Vec<String> = sightings.iter().map(|s| s.ip.to_string()).collect();
+        assert_eq!(vec!["192.0.2.1", "192.0.2.2", "192.0.2.3"], ips);
+    }
+}
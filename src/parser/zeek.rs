@@ -0,0 +1,302 @@
+use crate::parser::json::lookup;
+use crate::parser::{open_reader, utc_timestamp, Sighting};
+use crate::util::get_all_ips;
+use anyhow::{anyhow, Result};
+use chrono::DateTime;
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+/// The first line of a Zeek TSV log
+pub const HEADER: &[u8] = b"#separator";
+
+/// The connection endpoints read when no fields are given
+const ENDPOINTS: [&str; 2] = ["id.orig_h", "id.resp_h"];
+
+fn direction(field: &str) -> Option<&'static str> {
+    match field {
+        "id.orig_h" => Some("originator"),
+        "id.resp_h" => Some("responder"),
+        _ => None,
+    }
+}
+
+/// A `ts` value, either seconds since the epoch or an ISO 8601 string
+fn timestamp(ts: &str) -> Option<String> {
+    if let Ok(seconds) = ts.parse::<f64>() {
+        let nanos = (seconds.fract() * 1e9).round() as u32;
+        return DateTime::from_timestamp(seconds.trunc() as i64, nanos).map(|t| utc_timestamp(&t));
+    }
+    DateTime::parse_from_rfc3339(ts)
+        .ok()
+        .map(|t| utc_timestamp(&t))
+}
+
+/// The sightings of one log entry, given the value of each field
+fn entry_sightings<'a>(
+    values: impl Iterator<Item = (&'a str, Vec<&'a str>)>,
+    ts: Option<&str>,
+    uid: Option<&str>,
+) -> Vec<Sighting> {
+    let timestamp = ts.and_then(timestamp);
+    let mut sightings = vec![];
+    for (field, values) in values {
+        for ip in values.into_iter().flat_map(get_all_ips) {
+            let mut sighting = Sighting::new(ip).with("field", field);
+            if let Some(direction) = direction(field) {
+                sighting = sighting.with("direction", direction);
+            }
+            if let Some(timestamp) = &timestamp {
+                sighting = sighting.with("timestamp", timestamp.as_str());
+            }
+            if let Some(uid) = uid {
+                sighting = sighting.with("uid", uid);
+            }
+            sightings.push(sighting);
+        }
+    }
+    sightings
+}
+
+/// Extract the addresses from the given fields of a Zeek log file, which
+/// may be gzipped
+pub fn parse_zeek_file(file_path: &Path, fields: &[String]) -> Result<Vec<Sighting>> {
+    parse_zeek_reader(open_reader(file_path)?, fields)
+}
+
+/// Extract the addresses from the given fields of a Zeek TSV or JSON log.
+///
+/// TSV logs are read with the separator and fields of their `#` headers.
+/// The connection endpoints `id.orig_h` and `id.resp_h` are read when no
+/// fields are given. Each address records its field, the `direction` of
+/// an endpoint, and the `ts` and `uid` of its entry as context. Invalid
+/// JSON entries are skipped with a warning naming their line.
+pub fn parse_zeek_reader<R: Read>(reader: R, fields: &[String]) -> Result<Vec<Sighting>> {
+    let fields: Vec<&str> = if fields.is_empty() {
+        ENDPOINTS.to_vec()
+    } else {
+        fields.iter().map(String::as_str).collect()
+    };
+    let mut separator = String::from("\t");
+    let mut set_separator = String::from(",");
+    let mut unset_field = String::from("-");
+    let mut empty_field = String::from("(empty)");
+    let mut names: Vec<String> = vec![];
+    let mut reader = BufReader::new(reader);
+    let mut sightings = vec![];
+    let mut bytes = vec![];
+    let mut line_number = 0;
+    loop {
+        bytes.clear();
+        let read = reader
+            .read_until(b'\n', &mut bytes)
+            .map_err(|e| anyhow!("Unable to read Zeek log: {}", e))?;
+        if read == 0 {
+            break;
+        }
+        line_number += 1;
+        // Zeek does not escape every field, such as user agents
+        let line = String::from_utf8_lossy(&bytes);
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.trim_start().starts_with('{') {
+            match serde_json::from_str::<Value>(line) {
+                Ok(entry) => sightings.extend(json_entry(&entry, &fields)),
+                Err(e) => eprintln!("Skipping invalid Zeek JSON at line {}: {}", line_number, e),
+            }
+            continue;
+        }
+        if let Some(value) = line.strip_prefix("#separator ") {
+            separator = unescape(value);
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('#') {
+            let mut parts = header.split(separator.as_str());
+            let value = |parts: std::str::Split<&str>| parts.map(String::from).collect::<Vec<_>>();
+            match parts.next() {
+                Some("fields") => names = value(parts),
+                Some("set_separator") => set_separator = value(parts).concat(),
+                Some("unset_field") => unset_field = value(parts).concat(),
+                Some("empty_field") => empty_field = value(parts).concat(),
+                _ => (),
+            }
+            continue;
+        }
+        if line.is_empty() {
+            continue;
+        }
+        let row: Vec<&str> = line.split(separator.as_str()).collect();
+        let get = |name: &str| {
+            names
+                .iter()
+                .position(|n| n == name)
+                .and_then(|i| row.get(i).copied())
+                .filter(|value| *value != unset_field && *value != empty_field)
+        };
+        let values = fields.iter().filter_map(|field| {
+            get(field).map(|value| (*field, value.split(set_separator.as_str()).collect()))
+        });
+        sightings.extend(entry_sightings(values, get("ts"), get("uid")));
+    }
+    Ok(sightings)
+}
+
+fn json_entry(entry: &Value, fields: &[&str]) -> Vec<Sighting> {
+    // Fields are flat, such as "id.orig_h", unless Zeek was configured to
+    // nest them
+    let get = |field: &str| -> Vec<&Value> {
+        if let Some(value) = entry.get(field) {
+            return vec![value];
+        }
+        let mut values = vec![];
+        lookup(entry, &field.split('.').collect::<Vec<&str>>(), &mut values);
+        values
+    };
+    let strings = |values: Vec<&'_ Value>| -> Vec<String> {
+        values
+            .into_iter()
+            .flat_map(|value| match value {
+                Value::Array(items) => items.iter().collect(),
+                _ => vec![value],
+            })
+            .filter_map(|value| match value {
+                Value::String(s) => Some(s.to_string()),
+                Value::Number(n) => Some(n.to_string()),
+                _ => None,
+            })
+            .collect()
+    };
+    let ts = strings(get("ts")).into_iter().next();
+    let uid = strings(get("uid")).into_iter().next();
+    let values: Vec<(&str, Vec<String>)> = fields
+        .iter()
+        .map(|field| (*field, strings(get(field))))
+        .collect();
+    entry_sightings(
+        values
+            .iter()
+            .map(|(field, values)| (*field, values.iter().map(String::as_str).collect())),
+        ts.as_deref(),
+        uid.as_deref(),
+    )
+}
+
+/// Decode the `\x09` style escapes of a header value
+fn unescape(value: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = value;
+    while let Some(index) = rest.find("\\x") {
+        decoded.push_str(&rest[..index]);
+        let hex = rest.get(index + 2..index + 4);
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(byte) => {
+                decoded.push(byte as char);
+                rest = &rest[index + 4..];
+            }
+            None => {
+                decoded.push_str("\\x");
+                rest = &rest[index + 2..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CONN: &str = "#separator \\x09
+#set_separator\t,
+#empty_field\t(empty)
+#unset_field\t-
+#path\tconn
+#fields\tts\tuid\tid.orig_h\tid.orig_p\tid.resp_h\tid.resp_p\tproto
+#types\ttime\tstring\taddr\tport\taddr\tport\tenum
+1680343200.250000\tCHhAvVGS1DHFjwGM9\t10.0.0.5\t52144\t198.51.100.7\t443\ttcp
+1680343260.000000\tC4J4Th3PJpwUYZZ6gc\t2001:db8::5\t5353\t-\t53\tudp
+#close\t2023-04-01-11-00-00
+";
+
+    fn found(sightings: &[Sighting]) -> Vec<(String, String)> {
+        sightings
+            .iter()
+            .map(|s| (s.ip.to_string(), s.context["direction"].clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_zeek_tsv() {
+        let sightings = parse_zeek_reader(CONN.as_bytes(), &[]).unwrap();
+        assert_eq!(
+            vec![
+                (String::from("10.0.0.5"), String::from("originator")),
+                (String::from("198.51.100.7"), String::from("responder")),
+                (String::from("2001:db8::5"), String::from("originator")),
+            ],
+            found(&sightings)
+        );
+        assert_eq!("2023-04-01T10:00:00Z", sightings[1].context["timestamp"]);
+        assert_eq!("CHhAvVGS1DHFjwGM9", sightings[1].context["uid"]);
+        assert_eq!("id.resp_h", sightings[1].context["field"]);
+    }
+
+    #[test]
+    fn test_parse_zeek_tsv_set_field() {
+        let dns = "#separator \\x09\n#set_separator\t,\n#fields\tts\tuid\tanswers\n\
+            1680343200.0\tCx\t192.0.2.1,example.com,192.0.2.2\n";
+        let sightings = parse_zeek_reader(dns.as_bytes(), &[String::from("answers")]).unwrap();
+        assert_eq!(2, sightings.len());
+        assert!(!sightings[0].context.contains_key("direction"));
+    }
+
+    #[test]
+    fn test_parse_zeek_json() {
+        let input = r#"{"ts":1680343200.25,"uid":"CHhAvVGS1DHFjwGM9","id.orig_h":"10.0.0.5","id.orig_p":52144,"id.resp_h":"198.51.100.7","id.resp_p":443}
+{"ts":"2023-04-01T10:01:00.000000Z","uid":"C4J4Th3PJpwUYZZ6gc","id":{"orig_h":"2001:db8::5","resp_h":"192.0.2.1"}}
+"#;
+        let sightings = parse_zeek_reader(input.as_bytes(), &[]).unwrap();
+        assert_eq!(
+            vec![
+                (String::from("10.0.0.5"), String::from("originator")),
+                (String::from("198.51.100.7"), String::from("responder")),
+                (String::from("2001:db8::5"), String::from("originator")),
+                (String::from("192.0.2.1"), String::from("responder")),
+            ],
+            found(&sightings)
+        );
+        assert_eq!("2023-04-01T10:00:00Z", sightings[0].context["timestamp"]);
+        assert_eq!("2023-04-01T10:01:00Z", sightings[3].context["timestamp"]);
+        assert_eq!("C4J4Th3PJpwUYZZ6gc", sightings[3].context["uid"]);
+    }
+
+    #[test]
+    fn test_parse_zeek_json_invalid() {
+        let input = r#"{"id.orig_h":"10.0.0.5","id.resp_h":"198.51.100.7"}
+{"id.orig_h":"10.0.0.6","id.resp_h":
+{"id.orig_h":"10.0.0.7","id.resp_h":"192.0.2.1"}
+"#;
+        let sightings = parse_zeek_reader(input.as_bytes(), &[]).unwrap();
+        let ips: Vec<String> = sightings.iter().map(|s| s.ip.to_string()).collect();
+        assert_eq!(
+            vec!["10.0.0.5", "198.51.100.7", "10.0.0.7", "192.0.2.1"],
+            ips
+        );
+    }
+
+    #[test]
+    fn test_parse_zeek_invalid_utf8() {
+        let input = b"#separator \\x09\n#fields\tts\tid.orig_h\tuser_agent\n\
+            1\t192.0.2.1\tcurl\n2\t192.0.2.2\t\xffbot\n3\t192.0.2.3\twget\n";
+        let sightings = parse_zeek_reader(&input[..], &[String::from("id.orig_h")]).unwrap();
+        let ips: Vec<String> = sightings.iter().map(|s| s.ip.to_string()).collect();
+        assert_eq!(vec!["192.0.2.1", "192.0.2.2", "192.0.2.3"], ips);
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!("\t", unescape("\\x09"));
+        assert_eq!("a|b", unescape("a\\x7cb"));
+        assert_eq!("\\xZZ", unescape("\\xZZ"));
+    }
+}
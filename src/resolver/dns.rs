@@ -0,0 +1,499 @@
+use crate::util::classify::reverse_name;
+use anyhow::{anyhow, Result};
+use dns_lookup::LookupErrorKind;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const RCODE_NXDOMAIN: u8 = 3;
+
+/// A record from the answer section of a response
+#[derive(Debug, PartialEq)]
+enum Answer {
+    Address(IpAddr),
+    Name(String),
+    Other,
+}
+
+/// Looks up hostnames and PTR names with the system resolver, or with
+/// queries over UDP to a DNS server when one is given. Answers are cached
+/// and the cache is shared between clones.
+#[derive(Clone, Debug)]
+pub struct DnsResolver {
+    server: Option<SocketAddr>,
+    timeout: Duration,
+    hosts: Arc<Mutex<HashMap<String, Vec<IpAddr>>>>,
+    names: Arc<Mutex<HashMap<IpAddr, Option<String>>>>,
+}
+
+impl Default for DnsResolver {
+    fn default() -> Self {
+        DnsResolver::new(None, Duration::from_secs(5))
+    }
+}
+
+impl DnsResolver {
+    /// A resolver using the given DNS server, or the system resolver, that
+    /// waits up to the timeout for each lookup
+    pub fn new(server: Option<SocketAddr>, timeout: Duration) -> DnsResolver {
+        DnsResolver {
+            server,
+            timeout,
+            hosts: Arc::new(Mutex::new(HashMap::new())),
+            names: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// The IPv4 and IPv6 addresses of a hostname, or none if it does not
+    /// exist
+    pub fn lookup_host(&self, name: &str) -> Result<Vec<IpAddr>> {
+        if let Some(ips) = self.hosts.lock().unwrap().get(name) {
+            return Ok(ips.clone());
+        }
+        let ips = match self.server {
+            Some(server) => {
+                let mut ips = vec![];
+                for record_type in [TYPE_A, TYPE_AAAA] {
+                    ips.extend(
+                        self.query(server, name, record_type)?
+                            .into_iter()
+                            .filter_map(|answer| match answer {
+                                Answer::Address(ip) => Some(ip),
+                                _ => None,
+                            }),
+                    );
+                }
+                ips
+            }
+            None => {
+                let host = name.to_string();
+                self.with_timeout(name, move || system_lookup_host(&host))?
+            }
+        };
+        self.hosts
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), ips.clone());
+        Ok(ips)
+    }
+
+    /// The PTR name of an address, without the trailing dot, or `None` if
+    /// it has none. Failed lookups are errors and are not cached.
+    pub fn lookup_ptr(&self, ip: IpAddr) -> Result<Option<String>> {
+        if let Some(name) = self.names.lock().unwrap().get(&ip) {
+            return Ok(name.clone());
+        }
+        let name = match self.server {
+            Some(server) => self
+                .query(server, &reverse_name(&ip), TYPE_PTR)?
+                .into_iter()
+                .find_map(|answer| match answer {
+                    Answer::Name(name) => Some(name),
+                    _ => None,
+                }),
+            // Without NI_NAMEREQD, the system resolver gives back the address
+            // when there is no name, but still fails when the lookup does
+            None => {
+                let (host, _) =
+                    self.with_timeout(&ip.to_string(), move || {
+                        Ok(dns_lookup::getnameinfo(&(ip, 0).into(), 0)
+                            .map_err(std::io::Error::from)?)
+                    })?;
+                Some(host).filter(|host| host.parse::<IpAddr>().is_err())
+            }
+        };
+        self.names.lock().unwrap().insert(ip, name.clone());
+        Ok(name)
+    }
+
+    /// Add the PTR names saved by [`DnsResolver::save_ptr_cache`] to the
+    /// cache, if the file exists
+    pub fn load_ptr_cache(&self, path: &Path) -> Result<()> {
+        if !path.exists() {
+            return Ok(());
+        }
+        let saved: BTreeMap<IpAddr, Option<String>> =
+            serde_json::from_reader(std::fs::File::open(path)?)
+                .map_err(|e| anyhow!("Invalid PTR cache {}: {}", path.display(), e))?;
+        self.names.lock().unwrap().extend(saved);
+        Ok(())
+    }
+
+    /// Save the cached PTR names, including addresses without one, as a
+    /// JSON object keyed by address
+    pub fn save_ptr_cache(&self, path: &Path) -> Result<()> {
+        let names: BTreeMap<IpAddr, Option<String>> = self
+            .names
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(ip, name)| (*ip, name.clone()))
+            .collect();
+        serde_json::to_writer_pretty(std::fs::File::create(path)?, &names)?;
+        Ok(())
+    }
+
+    /// Run a lookup of the system resolver, which has no timeout of its own,
+    /// on another thread, leaving it behind if it does not finish in time
+    fn with_timeout<T: Send + 'static>(
+        &self,
+        name: &str,
+        lookup: impl FnOnce() -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || sender.send(lookup()));
+        receiver
+            .recv_timeout(self.timeout)
+            .unwrap_or_else(|_| Err(anyhow!("DNS lookup of {} timed out", name)))
+    }
+
+    /// Send a query to the server, waiting for the answer until the timeout
+    fn query(&self, server: SocketAddr, name: &str, record_type: u16) -> Result<Vec<Answer>> {
+        let bind: SocketAddr = match server {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(bind)?;
+        socket.connect(server)?;
+        let id = query_id(name, record_type);
+        socket.send(&encode_query(id, name, record_type)?)?;
+
+        let deadline = Instant::now() + self.timeout;
+        let mut buffer = [0; 4096];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(anyhow!("DNS query for {} timed out", name));
+            }
+            socket.set_read_timeout(Some(remaining))?;
+            let length = match socket.recv(&mut buffer) {
+                Ok(length) => length,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Err(anyhow!("DNS query for {} timed out", name))
+                }
+                Err(e) => return Err(e.into()),
+            };
+            // Responses to other queries are ignored
+            if let Some(answers) = decode_response(&buffer[..length], id)? {
+                return Ok(answers);
+            }
+        }
+    }
+}
+
+/// The distinct addresses of a hostname from the system resolver, or none
+/// if the name does not exist
+fn system_lookup_host(name: &str) -> Result<Vec<IpAddr>> {
+    let addrs = match dns_lookup::getaddrinfo(Some(name), None, None) {
+        Ok(addrs) => addrs,
+        Err(e) if matches!(e.kind(), LookupErrorKind::NoName | LookupErrorKind::NoData) => {
+            return Ok(vec![])
+        }
+        Err(e) => return Err(std::io::Error::from(e).into()),
+    };
+    let mut ips: Vec<IpAddr> = vec![];
+    for addr in addrs {
+        let ip = addr?.sockaddr.ip();
+        if !ips.contains(&ip) {
+            ips.push(ip);
+        }
+    }
+    Ok(ips)
+}
+
+/// An id for a query, varied by name, type and time so that stale answers
+/// are not mistaken for it
+fn query_id(name: &str, record_type: u16) -> u16 {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    name.bytes().fold(nanos ^ record_type as u32, |id, b| {
+        id.rotate_left(5) ^ b as u32
+    }) as u16
+}
+
+/// A recursive query for the records of a name
+fn encode_query(id: u16, name: &str, record_type: u16) -> Result<Vec<u8>> {
+    let mut message = vec![];
+    message.extend(id.to_be_bytes());
+    // Recursion desired, with one question
+    message.extend([0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(anyhow!("Invalid hostname: {}", name));
+        }
+        message.push(label.len() as u8);
+        message.extend(label.as_bytes());
+    }
+    message.push(0);
+    message.extend(record_type.to_be_bytes());
+    message.extend(CLASS_IN.to_be_bytes());
+    Ok(message)
+}
+
+/// The answers of a response to the query with the given id, or `None` if
+/// it answers another query
+fn decode_response(message: &[u8], id: u16) -> Result<Option<Vec<Answer>>> {
+    let truncated = || anyhow!("Truncated DNS response");
+    let u16_at = |offset: usize| -> Result<u16> {
+        message
+            .get(offset..offset + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .ok_or_else(truncated)
+    };
+    if message.len() < 12 || u16_at(0)? != id || message[2] & 0x80 == 0 {
+        return Ok(None);
+    }
+    match message[3] & 0x0f {
+        0 => (),
+        RCODE_NXDOMAIN => return Ok(Some(vec![])),
+        rcode => return Err(anyhow!("DNS server returned error code {}", rcode)),
+    }
+
+    let mut offset = 12;
+    for _ in 0..u16_at(4)? {
+        offset = skip_name(message, offset)? + 4;
+    }
+    let mut answers = vec![];
+    for _ in 0..u16_at(6)? {
+        offset = skip_name(message, offset)?;
+        let record_type = u16_at(offset)?;
+        let length = u16_at(offset + 8)? as usize;
+        let data = message
+            .get(offset + 10..offset + 10 + length)
+            .ok_or_else(truncated)?;
+        answers.push(match (record_type, data.len()) {
+            (TYPE_A, 4) => Answer::Address(IpAddr::from(<[u8; 4]>::try_from(data)?)),
+            (TYPE_AAAA, 16) => Answer::Address(IpAddr::from(<[u8; 16]>::try_from(data)?)),
+            (TYPE_PTR, _) => Answer::Name(read_name(message, offset + 10)?),
+            _ => Answer::Other,
+        });
+        offset += 10 + length;
+    }
+    Ok(Some(answers))
+}
+
+/// The name at an offset, following compression pointers
+fn read_name(message: &[u8], mut offset: usize) -> Result<String> {
+    let mut labels = vec![];
+    // Pointers must lead backwards, so that they cannot loop
+    let mut limit = offset;
+    loop {
+        let length = *message
+            .get(offset)
+            .ok_or_else(|| anyhow!("Truncated DNS response"))? as usize;
+        if length == 0 {
+            return Ok(labels.join("."));
+        }
+        if length & 0xc0 == 0xc0 {
+            let pointer = u16::from_be_bytes([
+                length as u8 & 0x3f,
+                *message
+                    .get(offset + 1)
+                    .ok_or_else(|| anyhow!("Truncated DNS response"))?,
+            ]) as usize;
+            if pointer >= limit {
+                return Err(anyhow!("Invalid DNS name pointer"));
+            }
+            offset = pointer;
+            limit = pointer;
+            continue;
+        }
+        let label = message
+            .get(offset + 1..offset + 1 + length)
+            .ok_or_else(|| anyhow!("Truncated DNS response"))?;
+        labels.push(String::from_utf8_lossy(label).to_string());
+        offset += 1 + length;
+    }
+}
+
+/// The offset after a name, which may end with a compression pointer
+fn skip_name(message: &[u8], mut offset: usize) -> Result<usize> {
+    loop {
+        match message.get(offset) {
+            None => return Err(anyhow!("Truncated DNS response")),
+            Some(0) => return Ok(offset + 1),
+            Some(length) if length & 0xc0 == 0xc0 => return Ok(offset + 2),
+            Some(length) => offset += 1 + *length as usize,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    /// A stand-in DNS server answering A queries with 192.0.2.1, AAAA
+    /// queries with no records, PTR queries for 192.0.2.1 with
+    /// scanner.example.com, and other queries with NXDOMAIN
+    fn stand_in_server(queries: usize) -> (SocketAddr, thread::JoinHandle<()>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let mut buffer = [0; 512];
+            for _ in 0..queries {
+                let (length, peer) = socket.recv_from(&mut buffer).unwrap();
+                let query = &buffer[..length];
+                let question = &query[12..];
+                let record_type = u16::from_be_bytes([query[length - 4], query[length - 3]]);
+                let missing = question.windows(7).any(|w| w == b"missing")
+                    || (record_type == TYPE_PTR
+                        && !question.starts_with(b"\x011\x012\x010\x03192"));
+
+                let mut response = query[..2].to_vec();
+                response.extend([0x81, if missing { 0x83 } else { 0x80 }, 0, 1]);
+                let answers = u16::from(matches!(record_type, TYPE_A | TYPE_PTR) && !missing);
+                response.extend(answers.to_be_bytes());
+                response.extend([0, 0, 0, 0]);
+                response.extend(question);
+                if answers > 0 && record_type == TYPE_PTR {
+                    response.extend([0xc0, 12]);
+                    response.extend(TYPE_PTR.to_be_bytes());
+                    response.extend(CLASS_IN.to_be_bytes());
+                    response.extend([0, 0, 0x0e, 0x10, 0, 21]);
+                    response.extend(b"\x07scanner\x07example\x03com\x00");
+                } else if answers > 0 {
+                    // A pointer to the name of the question
+                    response.extend([0xc0, 12]);
+                    response.extend(TYPE_A.to_be_bytes());
+                    response.extend(CLASS_IN.to_be_bytes());
+                    response.extend([0, 0, 0x0e, 0x10, 0, 4, 192, 0, 2, 1]);
+                }
+                socket.send_to(&response, peer).unwrap();
+            }
+        });
+        (addr, handle)
+    }
+
+    #[test]
+    fn test_lookup_host() {
+        let (server, handle) = stand_in_server(4);
+        let resolver = DnsResolver::new(Some(server), Duration::from_secs(5));
+        let expected = vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))];
+        assert_eq!(expected, resolver.lookup_host("c2.example.com").unwrap());
+        assert!(resolver.lookup_host("missing.example").unwrap().is_empty());
+        // Cached answers are not queried again
+        assert_eq!(
+            expected,
+            resolver.clone().lookup_host("c2.example.com").unwrap()
+        );
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_lookup_ptr() {
+        let (server, handle) = stand_in_server(2);
+        let resolver = DnsResolver::new(Some(server), Duration::from_secs(5));
+        let scanner = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let other = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7));
+        assert_eq!(
+            Some(String::from("scanner.example.com")),
+            resolver.lookup_ptr(scanner).unwrap()
+        );
+        assert_eq!(None, resolver.lookup_ptr(other).unwrap());
+        assert_eq!(None, resolver.lookup_ptr(other).unwrap());
+        handle.join().unwrap();
+
+        let cache = tempfile::NamedTempFile::new().unwrap();
+        resolver.save_ptr_cache(cache.path()).unwrap();
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let cached = DnsResolver::new(
+            Some(silent.local_addr().unwrap()),
+            Duration::from_millis(50),
+        );
+        cached.load_ptr_cache(cache.path()).unwrap();
+        assert_eq!(
+            Some(String::from("scanner.example.com")),
+            cached.lookup_ptr(scanner).unwrap()
+        );
+        assert_eq!(None, cached.lookup_ptr(other).unwrap());
+    }
+
+    #[test]
+    fn test_read_name() {
+        let message = b"\x07example\x03com\x00\x07scanner\xc0\x00\xc0\x17";
+        assert_eq!("example.com", read_name(message, 0).unwrap());
+        assert_eq!("scanner.example.com", read_name(message, 13).unwrap());
+        // A pointer to itself
+        assert!(read_name(message, 23).is_err());
+    }
+
+    #[test]
+    fn test_lookup_host_timeout() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let resolver = DnsResolver::new(
+            Some(silent.local_addr().unwrap()),
+            Duration::from_millis(50),
+        );
+        assert!(resolver.lookup_host("c2.example.com").is_err());
+    }
+
+    #[test]
+    fn test_lookup_host_system() {
+        let resolver = DnsResolver::default();
+        let ips = resolver.lookup_host("localhost").unwrap();
+        assert!(ips.contains(&IpAddr::V4(Ipv4Addr::LOCALHOST)));
+        // RFC 6761 reserves .invalid, so it never exists
+        assert!(resolver.lookup_host("nothing.invalid").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_lookup_ptr_system() {
+        let resolver = DnsResolver::default();
+        let name = resolver
+            .lookup_ptr(IpAddr::V4(Ipv4Addr::LOCALHOST))
+            .unwrap();
+        assert!(name.is_some_and(|name| name.parse::<IpAddr>().is_err()));
+    }
+
+    #[test]
+    fn test_with_timeout() {
+        let resolver = DnsResolver::new(None, Duration::from_millis(50));
+        assert_eq!(1, resolver.with_timeout("fast", || Ok(1)).unwrap());
+        let slow = resolver.with_timeout("slow.example.com", || {
+            thread::sleep(Duration::from_secs(1));
+            Ok(1)
+        });
+        assert_eq!(
+            "DNS lookup of slow.example.com timed out",
+            slow.unwrap_err().to_string()
+        );
+        assert!(resolver
+            .with_timeout("failed", || Err::<(), _>(anyhow!("no server")))
+            .is_err());
+    }
+
+    #[test]
+    fn test_encode_query() {
+        let query = encode_query(0x1234, "a.bc.", TYPE_AAAA).unwrap();
+        assert_eq!(
+            vec![0x12, 0x34, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 1, b'a', 2, b'b', b'c', 0, 0, 28, 0, 1],
+            query
+        );
+        assert!(encode_query(1, "a..b", TYPE_A).is_err());
+    }
+
+    #[test]
+    fn test_decode_response_other_id() {
+        let response = [0x12, 0x34, 0x81, 0x80, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(None, decode_response(&response, 0x4321).unwrap());
+        assert_eq!(Some(vec![]), decode_response(&response, 0x1234).unwrap());
+        assert!(
+            decode_response(&[0x12, 0x34, 0x81, 0x82, 0, 0, 0, 0, 0, 0, 0, 0], 0x1234).is_err()
+        );
+    }
+}
@@ -1,7 +1,8 @@
 use anyhow::Result;
-use serde::ser::SerializeStruct;
+use serde::ser::SerializeMap;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::BTreeMap;
 use std::net::IpAddr;
 
 #[derive(Deserialize, Serialize, Default)]
@@ -14,7 +15,7 @@ impl IpApiRecords {
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize, Default)]
 pub struct IpApiRecord {
     pub query: Option<String>,
     pub status: Option<String>,
@@ -39,6 +40,9 @@ pub struct IpApiRecord {
     pub mobile: Option<bool>,
     pub proxy: Option<bool>,
     pub hosting: Option<bool>,
+    /// Extra columns computed locally, written after the ip-api fields
+    #[serde(skip)]
+    pub context: BTreeMap<String, String>,
 }
 
 impl IpApiRecord {
@@ -106,6 +110,7 @@ impl IpApiRecord {
             mobile: value.get("mobile").and_then(|v| v.as_bool()),
             proxy: value.get("proxy").and_then(|v| v.as_bool()),
             hosting: value.get("hosting").and_then(|v| v.as_bool()),
+            context: BTreeMap::new(),
         }
     }
 }
@@ -115,30 +120,33 @@ impl Serialize for IpApiRecord {
     where
         S: serde::Serializer,
     {
-        let mut state = serializer.serialize_struct("IpApiRecord", 20)?;
-        state.serialize_field("query", &self.query)?;
-        state.serialize_field("status", &self.status)?;
-        state.serialize_field("continent", &self.continent)?;
-        state.serialize_field("continentCode", &self.continent_code)?;
-        state.serialize_field("country", &self.country)?;
-        state.serialize_field("countryCode", &self.country_code)?;
-        state.serialize_field("region", &self.region)?;
-        state.serialize_field("region_name", &self.region_name)?;
-        state.serialize_field("city", &self.city)?;
-        state.serialize_field("district", &self.district)?;
-        state.serialize_field("zip", &self.zip)?;
-        state.serialize_field("lat", &self.lat)?;
-        state.serialize_field("lon", &self.lon)?;
-        state.serialize_field("timezone", &self.timezone)?;
-        state.serialize_field("offset", &self.offset)?;
-        state.serialize_field("currency", &self.currency)?;
-        state.serialize_field("isp", &self.isp)?;
-        state.serialize_field("org", &self.org)?;
-        state.serialize_field("as", &self.asn)?;
-        state.serialize_field("asname", &self.asname)?;
-        state.serialize_field("mobile", &self.mobile)?;
-        state.serialize_field("proxy", &self.proxy)?;
-        state.serialize_field("hosting", &self.hosting)?;
+        let mut state = serializer.serialize_map(Some(23 + self.context.len()))?;
+        state.serialize_entry("query", &self.query)?;
+        state.serialize_entry("status", &self.status)?;
+        state.serialize_entry("continent", &self.continent)?;
+        state.serialize_entry("continentCode", &self.continent_code)?;
+        state.serialize_entry("country", &self.country)?;
+        state.serialize_entry("countryCode", &self.country_code)?;
+        state.serialize_entry("region", &self.region)?;
+        state.serialize_entry("regionName", &self.region_name)?;
+        state.serialize_entry("city", &self.city)?;
+        state.serialize_entry("district", &self.district)?;
+        state.serialize_entry("zip", &self.zip)?;
+        state.serialize_entry("lat", &self.lat)?;
+        state.serialize_entry("lon", &self.lon)?;
+        state.serialize_entry("timezone", &self.timezone)?;
+        state.serialize_entry("offset", &self.offset)?;
+        state.serialize_entry("currency", &self.currency)?;
+        state.serialize_entry("isp", &self.isp)?;
+        state.serialize_entry("org", &self.org)?;
+        state.serialize_entry("as", &self.asn)?;
+        state.serialize_entry("asname", &self.asname)?;
+        state.serialize_entry("mobile", &self.mobile)?;
+        state.serialize_entry("proxy", &self.proxy)?;
+        state.serialize_entry("hosting", &self.hosting)?;
+        for (key, value) in &self.context {
+            state.serialize_entry(key, value)?;
+        }
         state.end()
     }
 }
@@ -171,7 +179,7 @@ impl Resolver {
             .collect()
     }
 
-    fn allowed_columns() -> Vec<String> {
+    pub fn allowed_columns() -> Vec<String> {
         vec![
             String::from("query"),
             String::from("status"),
@@ -217,7 +225,10 @@ impl Resolver {
 
             match resp.json::<Value>() {
                 Ok(record) => {
-                    let record = IpApiRecord::from_value(&record);
+                    let mut record = IpApiRecord::from_value(&record);
+                    if record.query.is_none() {
+                        record.query = Some(ip_addr.to_string());
+                    }
                     all_responses.records.push(record);
                 }
                 Err(e) => {
@@ -258,4 +269,19 @@ mod test {
         let actual = Resolver::check_columns(sample.to_owned());
         assert_eq!(sample, actual);
     }
+
+    #[test]
+    fn test_serialize_context() {
+        let mut record = IpApiRecord {
+            query: Some(String::from("10.0.0.1")),
+            ..Default::default()
+        };
+        record
+            .context
+            .insert(String::from("scope"), String::from("private"));
+
+        let json = serde_json::to_string(&record).unwrap();
+        assert!(json.starts_with("{\"query\":\"10.0.0.1\","));
+        assert!(json.ends_with("\"hosting\":null,\"scope\":\"private\"}"));
+    }
 }
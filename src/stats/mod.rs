@@ -0,0 +1,259 @@
+use crate::resolver::ip_api::{IpApiRecord, IpApiRecords};
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::IpAddr;
+
+/// How often a value was seen, counting every occurrence of an address and
+/// the distinct addresses
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Count {
+    pub value: String,
+    pub occurrences: usize,
+    pub addresses: usize,
+}
+
+/// Occurrence counts per address and per country, ASN, organisation and
+/// flag of the resolved records
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Stats {
+    pub occurrences: usize,
+    pub addresses: usize,
+    pub ips: Vec<Count>,
+    pub countries: Vec<Count>,
+    pub asns: Vec<Count>,
+    pub orgs: Vec<Count>,
+    pub flags: Vec<Count>,
+}
+
+impl Stats {
+    /// Count the extracted addresses, including repeats, against their
+    /// resolved records. Each list is sorted by occurrences and limited to
+    /// the `top` most frequent values, except for the flags.
+    pub fn new(ips: &[IpAddr], records: &IpApiRecords, top: Option<usize>) -> Stats {
+        let mut seen: Vec<(IpAddr, usize)> = vec![];
+        let mut positions: HashMap<IpAddr, usize> = HashMap::new();
+        for ip in ips {
+            match positions.get(ip) {
+                Some(position) => seen[*position].1 += 1,
+                None => {
+                    positions.insert(*ip, seen.len());
+                    seen.push((*ip, 1));
+                }
+            }
+        }
+
+        let mut by_ip: HashMap<IpAddr, &IpApiRecord> = HashMap::new();
+        for record in &records.records {
+            if let Some(Ok(ip)) = record.query.as_deref().map(str::parse::<IpAddr>) {
+                by_ip.entry(ip).or_insert(record);
+            }
+        }
+        let resolved: Vec<(&IpApiRecord, usize)> = seen
+            .iter()
+            .filter_map(|(ip, count)| by_ip.get(ip).map(|record| (*record, *count)))
+            .collect();
+
+        let ip_counts = seen
+            .iter()
+            .map(|(ip, count)| Count {
+                value: ip.to_string(),
+                occurrences: *count,
+                addresses: 1,
+            })
+            .collect();
+        let flags = ["proxy", "hosting", "mobile"]
+            .iter()
+            .map(|flag| {
+                let flagged: Vec<usize> = resolved
+                    .iter()
+                    .filter(|(record, _)| has_flag(record, flag))
+                    .map(|(_, count)| *count)
+                    .collect();
+                Count {
+                    value: flag.to_string(),
+                    occurrences: flagged.iter().sum(),
+                    addresses: flagged.len(),
+                }
+            })
+            .collect();
+
+        Stats {
+            occurrences: ips.len(),
+            addresses: seen.len(),
+            ips: top_n(ip_counts, top),
+            countries: top_n(tally(&resolved, |r| r.country.clone()), top),
+            asns: top_n(tally(&resolved, |r| r.asn.clone()), top),
+            orgs: top_n(tally(&resolved, |r| r.org.clone()), top),
+            flags,
+        }
+    }
+
+    /// Write the counts as plain text tables
+    pub fn write_text<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writeln!(
+            writer,
+            "{} occurrences of {} distinct addresses",
+            self.occurrences, self.addresses
+        )?;
+        for (title, counts) in [
+            ("IP", &self.ips),
+            ("Country", &self.countries),
+            ("ASN", &self.asns),
+            ("Organisation", &self.orgs),
+            ("Flag", &self.flags),
+        ] {
+            writeln!(writer)?;
+            writeln!(
+                writer,
+                "{:>11} {:>9}  {}",
+                "Occurrences", "Addresses", title
+            )?;
+            for count in counts {
+                writeln!(
+                    writer,
+                    "{:>11} {:>9}  {}",
+                    count.occurrences, count.addresses, count.value
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn has_flag(record: &IpApiRecord, flag: &str) -> bool {
+    let value = match flag {
+        "proxy" => record.proxy,
+        "hosting" => record.hosting,
+        "mobile" => record.mobile,
+        _ => None,
+    };
+    value.unwrap_or(false)
+}
+
+fn tally<F>(resolved: &[(&IpApiRecord, usize)], value: F) -> Vec<Count>
+where
+    F: Fn(&IpApiRecord) -> Option<String>,
+{
+    let mut counts: HashMap<String, (usize, usize)> = HashMap::new();
+    for (record, occurrences) in resolved {
+        if let Some(value) = value(record) {
+            let count = counts.entry(value).or_insert((0, 0));
+            count.0 += occurrences;
+            count.1 += 1;
+        }
+    }
+    counts
+        .into_iter()
+        .map(|(value, (occurrences, addresses))| Count {
+            value,
+            occurrences,
+            addresses,
+        })
+        .collect()
+}
+
+fn top_n(mut counts: Vec<Count>, top: Option<usize>) -> Vec<Count> {
+    counts.sort_by(|a, b| {
+        b.occurrences
+            .cmp(&a.occurrences)
+            .then_with(|| a.value.cmp(&b.value))
+    });
+    if let Some(top) = top {
+        counts.truncate(top);
+    }
+    counts
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(query: &str, country: &str, asn: &str, hosting: bool) -> IpApiRecord {
+        IpApiRecord {
+            query: Some(String::from(query)),
+            country: Some(String::from(country)),
+            asn: Some(String::from(asn)),
+            org: Some(String::from(asn)),
+            hosting: Some(hosting),
+            ..Default::default()
+        }
+    }
+
+    fn sample() -> (Vec<IpAddr>, IpApiRecords) {
+        let ips = [
+            "1.1.1.1", "8.8.8.8", "1.1.1.1", "1.0.0.1", "1.1.1.1", "9.9.9.9",
+        ]
+        .iter()
+        .map(|ip| ip.parse().unwrap())
+        .collect();
+        let records = IpApiRecords {
+            records: vec![
+                record("1.1.1.1", "Australia", "AS13335 Cloudflare", true),
+                record("8.8.8.8", "United States", "AS15169 Google LLC", true),
+                record("1.0.0.1", "Australia", "AS13335 Cloudflare", false),
+            ],
+        };
+        (ips, records)
+    }
+
+    fn values(counts: &[Count]) -> Vec<(&str, usize, usize)> {
+        counts
+            .iter()
+            .map(|c| (c.value.as_str(), c.occurrences, c.addresses))
+            .collect()
+    }
+
+    #[test]
+    fn test_stats() {
+        let (ips, records) = sample();
+        let stats = Stats::new(&ips, &records, None);
+
+        assert_eq!(6, stats.occurrences);
+        assert_eq!(4, stats.addresses);
+        assert_eq!(
+            vec![
+                ("1.1.1.1", 3, 1),
+                ("1.0.0.1", 1, 1),
+                ("8.8.8.8", 1, 1),
+                ("9.9.9.9", 1, 1),
+            ],
+            values(&stats.ips)
+        );
+        assert_eq!(
+            vec![("Australia", 4, 2), ("United States", 1, 1)],
+            values(&stats.countries)
+        );
+        assert_eq!(
+            vec![("AS13335 Cloudflare", 4, 2), ("AS15169 Google LLC", 1, 1)],
+            values(&stats.asns)
+        );
+        assert_eq!(
+            vec![("proxy", 0, 0), ("hosting", 4, 2), ("mobile", 0, 0)],
+            values(&stats.flags)
+        );
+    }
+
+    #[test]
+    fn test_stats_top() {
+        let (ips, records) = sample();
+        let stats = Stats::new(&ips, &records, Some(1));
+        assert_eq!(vec![("1.1.1.1", 3, 1)], values(&stats.ips));
+        assert_eq!(vec![("Australia", 4, 2)], values(&stats.countries));
+        assert_eq!(3, stats.flags.len());
+    }
+
+    #[test]
+    fn test_write_text() {
+        let (ips, records) = sample();
+        let mut buffer = Vec::new();
+        Stats::new(&ips, &records, Some(2))
+            .write_text(&mut buffer)
+            .unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+        assert!(text.starts_with("6 occurrences of 4 distinct addresses\n"));
+        assert!(text.contains("          3         1  1.1.1.1\n"));
+        assert!(text.contains("          4         2  Australia\n"));
+    }
+}
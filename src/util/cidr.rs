@@ -0,0 +1,133 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// An IPv4 or IPv6 network prefix, such as `10.0.0.0/8` or `2001:db8::/32`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Create a prefix, clearing any host bits of the address
+    pub fn new(address: IpAddr, prefix_len: u8) -> Option<Cidr> {
+        if prefix_len > max_prefix_len(&address) {
+            return None;
+        }
+        let network = match address {
+            IpAddr::V4(v4) => IpAddr::V4((u32::from(v4) & v4_mask(prefix_len)).into()),
+            IpAddr::V6(v6) => IpAddr::V6((u128::from(v6) & v6_mask(prefix_len)).into()),
+        };
+        Some(Cidr {
+            network,
+            prefix_len,
+        })
+    }
+
+    pub fn network(&self) -> IpAddr {
+        self.network
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                u32::from(*ip) & v4_mask(self.prefix_len) == u32::from(network)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                u128::from(*ip) & v6_mask(self.prefix_len) == u128::from(network)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    /// Parse a prefix in `address/length` notation. A bare address is
+    /// treated as a prefix of a single host.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (address, prefix_len) = match s.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (s, None),
+        };
+        let address = address
+            .parse::<IpAddr>()
+            .map_err(|e| format!("Invalid network address {}: {}", address, e))?;
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse::<u8>()
+                .map_err(|e| format!("Invalid prefix length {}: {}", prefix_len, e))?,
+            None => max_prefix_len(&address),
+        };
+        Cidr::new(address, prefix_len).ok_or_else(|| format!("Prefix length out of range: {}", s))
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+pub(crate) fn max_prefix_len(address: &IpAddr) -> u8 {
+    match address {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+fn v4_mask(prefix_len: u8) -> u32 {
+    u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0)
+}
+
+fn v6_mask(prefix_len: u8) -> u128 {
+    u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn test_parse_cidr() {
+        let cidr = "10.1.2.3/8".parse::<Cidr>().unwrap();
+        assert_eq!(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), cidr.network());
+        assert_eq!(8, cidr.prefix_len());
+        assert_eq!("10.0.0.0/8", cidr.to_string());
+
+        let cidr = "2001:db8::1".parse::<Cidr>().unwrap();
+        assert_eq!(128, cidr.prefix_len());
+
+        assert_eq!(
+            "0.0.0.0/0",
+            "1.2.3.4/0".parse::<Cidr>().unwrap().to_string()
+        );
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("2001:db8::/129".parse::<Cidr>().is_err());
+        assert!("example.com/24".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/x".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn test_contains() {
+        let cidr = "172.16.0.0/12".parse::<Cidr>().unwrap();
+        assert!(cidr.contains(&IpAddr::V4(Ipv4Addr::new(172, 31, 255, 255))));
+        assert!(!cidr.contains(&IpAddr::V4(Ipv4Addr::new(172, 32, 0, 0))));
+        assert!(!cidr.contains(&IpAddr::V6(Ipv6Addr::LOCALHOST)));
+
+        let cidr = "fc00::/7".parse::<Cidr>().unwrap();
+        assert!(cidr.contains(&IpAddr::V6(Ipv6Addr::new(0xfdff, 0, 0, 0, 0, 0, 0, 1))));
+        assert!(!cidr.contains(&IpAddr::V6(Ipv6Addr::new(0xfe00, 0, 0, 0, 0, 0, 0, 1))));
+
+        let everything = "::/0".parse::<Cidr>().unwrap();
+        assert!(everything.contains(&IpAddr::V6(Ipv6Addr::LOCALHOST)));
+    }
+}
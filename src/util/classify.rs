@@ -0,0 +1,211 @@
+use crate::util::special_purpose::{special_purpose, SpecialPurposeBlock};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Names of the columns produced by [`AddressClass::to_columns`]
+pub const COLUMNS: &[&str] = &[
+    "family",
+    "scope",
+    "category",
+    "special_purpose",
+    "embedding",
+    "embedded_ipv4",
+    "reverse_name",
+];
+
+/// IPv6 transition mechanisms that carry an IPv4 address inside the IPv6 address
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Embedding {
+    /// `::ffff:0:0/96`, RFC 4291
+    Ipv4Mapped,
+    /// `2002::/16`, RFC 3056
+    SixToFour,
+    /// `2001::/32`, RFC 4380. The embedded address is the client's public
+    /// address, stored inverted in the last 32 bits.
+    Teredo,
+    /// `64:ff9b::/96`, RFC 6052
+    Nat64,
+}
+
+impl Embedding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Embedding::Ipv4Mapped => "ipv4-mapped",
+            Embedding::SixToFour => "6to4",
+            Embedding::Teredo => "teredo",
+            Embedding::Nat64 => "nat64",
+        }
+    }
+}
+
+impl fmt::Display for Embedding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Find the IPv4 address embedded in an IPv6 transition address
+pub fn embedded_ipv4(ip: &Ipv6Addr) -> Option<(Embedding, Ipv4Addr)> {
+    let bits = u128::from(*ip);
+    let low_32 = Ipv4Addr::from(bits as u32);
+    let segments = ip.segments();
+
+    if let Some(v4) = ip.to_ipv4_mapped() {
+        Some((Embedding::Ipv4Mapped, v4))
+    } else if segments[0] == 0x2002 {
+        Some((Embedding::SixToFour, Ipv4Addr::from((bits >> 80) as u32)))
+    } else if segments[0] == 0x2001 && segments[1] == 0 {
+        Some((Embedding::Teredo, Ipv4Addr::from(!(bits as u32))))
+    } else if bits >> 32 == 0x0064_ff9b_0000_0000_0000_0000 {
+        Some((Embedding::Nat64, low_32))
+    } else {
+        None
+    }
+}
+
+/// Owner name of the PTR record of the address, such as
+/// `4.3.2.1.in-addr.arpa` for `1.2.3.4`
+pub fn reverse_name(ip: &IpAddr) -> String {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, c, d] = v4.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        }
+        IpAddr::V6(v6) => {
+            let mut name = String::with_capacity(72);
+            for byte in v6.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", byte & 0x0f, byte >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+/// Facts about an address that are known without any network lookup
+#[derive(Debug, PartialEq)]
+pub struct AddressClass {
+    pub ip: IpAddr,
+    /// The most specific special-purpose block containing the address
+    pub special_purpose: Option<&'static SpecialPurposeBlock>,
+    pub embedding: Option<(Embedding, Ipv4Addr)>,
+}
+
+impl AddressClass {
+    pub fn family(&self) -> &'static str {
+        match self.ip {
+            IpAddr::V4(_) => "ipv4",
+            IpAddr::V6(_) => "ipv6",
+        }
+    }
+
+    /// Whether the address is globally reachable
+    pub fn is_public(&self) -> bool {
+        self.special_purpose
+            .map(|block| block.globally_reachable)
+            .unwrap_or(true)
+    }
+
+    /// The classification as output columns, named as in [`COLUMNS`].
+    /// Columns without a value are left out.
+    pub fn to_columns(&self) -> Vec<(&'static str, String)> {
+        let scope = if self.is_public() {
+            "public"
+        } else {
+            "private"
+        };
+        let mut columns = vec![
+            ("family", self.family().to_string()),
+            ("scope", scope.to_string()),
+        ];
+        if let Some(block) = self.special_purpose {
+            columns.push(("category", block.category.to_string()));
+            columns.push(("special_purpose", block.name.to_string()));
+        }
+        if let Some((embedding, v4)) = self.embedding {
+            columns.push(("embedding", embedding.to_string()));
+            columns.push(("embedded_ipv4", v4.to_string()));
+        }
+        columns.push(("reverse_name", reverse_name(&self.ip)));
+        columns
+    }
+}
+
+/// Classify an address using only local knowledge
+pub fn classify(ip: &IpAddr) -> AddressClass {
+    let embedding = match ip {
+        IpAddr::V4(_) => None,
+        IpAddr::V6(v6) => embedded_ipv4(v6),
+    };
+    AddressClass {
+        ip: *ip,
+        special_purpose: special_purpose(ip),
+        embedding,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_embedded_ipv4() {
+        let tests = vec![
+            ("::ffff:1.2.3.4", Some((Embedding::Ipv4Mapped, "1.2.3.4"))),
+            ("2002:0102:0304::1", Some((Embedding::SixToFour, "1.2.3.4"))),
+            (
+                "2001:0:4136:e378:8000:63bf:3fff:fdd2",
+                Some((Embedding::Teredo, "192.0.2.45")),
+            ),
+            ("64:ff9b::808:808", Some((Embedding::Nat64, "8.8.8.8"))),
+            ("2001:db8::1", None),
+            ("2001:4860:4860::8888", None),
+        ];
+        for (address, expected) in tests {
+            let v6 = address.parse::<Ipv6Addr>().unwrap();
+            let expected = expected.map(|(e, v4)| (e, v4.parse::<Ipv4Addr>().unwrap()));
+            assert_eq!(expected, embedded_ipv4(&v6), "{}", address);
+        }
+    }
+
+    #[test]
+    fn test_reverse_name() {
+        assert_eq!("4.3.2.1.in-addr.arpa", reverse_name(&ip("1.2.3.4")));
+        assert_eq!(
+            "b.a.9.8.7.6.5.0.4.0.0.0.3.0.0.0.2.0.0.0.1.0.0.0.0.0.0.0.1.2.3.4.ip6.arpa",
+            reverse_name(&ip("4321:0:1:2:3:4:567:89ab"))
+        );
+    }
+
+    #[test]
+    fn test_classify_columns() {
+        let columns = classify(&ip("8.8.8.8")).to_columns();
+        assert_eq!(
+            vec![
+                ("family", String::from("ipv4")),
+                ("scope", String::from("public")),
+                ("reverse_name", String::from("8.8.8.8.in-addr.arpa")),
+            ],
+            columns
+        );
+
+        let columns = classify(&ip("10.1.2.3")).to_columns();
+        assert!(columns.contains(&("scope", String::from("private"))));
+        assert!(columns.contains(&("category", String::from("private"))));
+        assert!(columns.contains(&("special_purpose", String::from("Private-Use"))));
+
+        let columns = classify(&ip("2002:0808:0808::1")).to_columns();
+        assert!(columns.contains(&("family", String::from("ipv6"))));
+        assert!(columns.contains(&("scope", String::from("public"))));
+        assert!(columns.contains(&("embedding", String::from("6to4"))));
+        assert!(columns.contains(&("embedded_ipv4", String::from("8.8.8.8"))));
+
+        for (name, _) in classify(&ip("::ffff:10.0.0.1")).to_columns() {
+            assert!(COLUMNS.contains(&name));
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::HashSet;
+
+lazy_static! {
+    // Labels of letters, digits and hyphens ending in a top-level domain
+    // that starts with a letter, so that dotted addresses do not match
+    static ref HOSTNAME: Regex = Regex::new(
+        r"(?i)\b(?:[a-z0-9](?:[a-z0-9-]{0,61}[a-z0-9])?\.)+[a-z](?:[a-z0-9-]{0,61}[a-z0-9])?\b"
+    )
+    .unwrap();
+    // Dots defanged in threat reports, such as evil[.]com or evil[dot]com
+    static ref DEFANGED_DOT: Regex = Regex::new(r"(?i)\[\.\]|\(\.\)|\{\.\}|\[dot\]").unwrap();
+}
+
+/// File extensions that look like top-level domains in file names such as
+/// `invoice.pdf` or `kernel32.dll`
+const FILE_EXTENSIONS: &[&str] = &[
+    "asp", "aspx", "bat", "bin", "cfg", "conf", "csv", "dat", "dll", "dmp", "doc", "docm", "docx",
+    "exe", "gif", "hta", "htm", "html", "ini", "jpeg", "jpg", "js", "json", "jsp", "lnk", "log",
+    "pdf", "php", "png", "ps1", "rar", "sys", "tmp", "txt", "vbs", "xls", "xlsx", "xml", "zip",
+];
+
+/// Whether the last label of a name is a file extension rather than a
+/// top-level domain
+fn is_file_name(name: &str) -> bool {
+    name.rsplit('.')
+        .next()
+        .is_some_and(|tld| FILE_EXTENSIONS.contains(&tld))
+}
+
+/// Find the distinct hostnames in the data, including the hosts of URLs
+/// and defanged names such as `evil[.]com`, in lowercase and in document
+/// order
+pub fn get_all_hostnames(data: &str) -> Vec<String> {
+    let data = DEFANGED_DOT.replace_all(data, ".");
+    let mut seen = HashSet::new();
+    HOSTNAME
+        .find_iter(&data)
+        .map(|m| m.as_str().to_ascii_lowercase())
+        .filter(|name| name.len() <= 253 && !is_file_name(name))
+        .filter(|name| seen.insert(name.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_get_all_hostnames() {
+        let data = "Beacons to hxxps://cdn.Evil-Updates[.]com/a.php?id=1 and \
+            c2.example(.)net:8443, then drops payload.exe from \
+            http://user@files.example.org/x.zip. Seen from 192.0.2.1 and \
+            cdn.evil-updates.com again.";
+        assert_eq!(
+            vec![
+                String::from("cdn.evil-updates.com"),
+                String::from("c2.example.net"),
+                String::from("files.example.org"),
+            ],
+            get_all_hostnames(data)
+        );
+    }
+
+    #[test]
+    fn test_get_all_hostnames_none() {
+        assert!(get_all_hostnames("10.0.0.1 1.2.3.4:80 2001:db8::1 no names").is_empty());
+    }
+}
@@ -1,40 +1,135 @@
+pub mod cidr;
+pub mod classify;
+pub mod hostname;
+pub mod prefix_set;
+pub mod scanner;
+pub mod special_purpose;
+
+use classify::embedded_ipv4;
 use lazy_static::lazy_static;
+use prefix_set::PrefixSet;
 use regex::Regex;
+use scanner::{scan_parallel, scan_parallel_with, Endpoint, CHUNK_SIZE};
+use special_purpose::SpecialPurposeFilter;
+use std::collections::HashSet;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
+/// How strictly IPv4 matches must be separated from the surrounding text
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Ipv4Strictness {
+    /// Accept any match, even inside longer runs of digits and dots
+    Lenient,
+    /// Reject matches that are part of a longer run of digits and dots,
+    /// such as `1.1.1.1234` or `10.0.0.1.5`. Trailing dots, as at the end
+    /// of a sentence, are allowed.
+    #[default]
+    Standard,
+    /// Also reject matches touching letters, `_` or `-`, and matches after
+    /// a `/` that follows a word, such as version strings in
+    /// `Chrome/120.0.0.1`
+    Strict,
+}
+
+/// How IPv6 transition addresses carrying an IPv4 address are handled
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnwrapMode {
+    /// Keep the IPv6 address as found
+    #[default]
+    Keep,
+    /// Use the embedded IPv4 address instead of the IPv6 address
+    Replace,
+    /// Keep the IPv6 address and add the embedded IPv4 address after it
+    Both,
+}
+
+/// The distinct addresses, in order of first appearance
 pub fn get_distinct_ips(ips: Vec<IpAddr>) -> Vec<IpAddr> {
-    let mut distinct_ips = vec![];
-    for ip in ips.into_iter() {
-        if !distinct_ips.contains(&ip) {
-            distinct_ips.push(ip);
-        }
-    }
-    distinct_ips
+    let mut seen = HashSet::with_capacity(ips.len());
+    ips.into_iter().filter(|ip| seen.insert(*ip)).collect()
 }
 
 pub fn get_routable_ips(ips: Vec<IpAddr>) -> Vec<IpAddr> {
-    // TODO Filter out common BOGON
-    ips.into_iter()
-        .filter(|x| !x.is_unspecified() && !x.is_multicast() && !x.is_loopback())
-        .collect()
+    filter_special_purpose_ips(ips, &SpecialPurposeFilter::default())
+}
+
+pub fn filter_special_purpose_ips(ips: Vec<IpAddr>, filter: &SpecialPurposeFilter) -> Vec<IpAddr> {
+    ips.into_iter().filter(|x| !filter.is_excluded(x)).collect()
 }
 
+/// Keep only the addresses contained in one of the allowed prefixes
+pub fn filter_allowed_ips(ips: Vec<IpAddr>, allowed: &PrefixSet) -> Vec<IpAddr> {
+    ips.into_iter().filter(|x| allowed.contains(x)).collect()
+}
+
+/// Drop the addresses contained in any of the denied prefixes
+pub fn filter_denied_ips(ips: Vec<IpAddr>, denied: &PrefixSet) -> Vec<IpAddr> {
+    ips.into_iter().filter(|x| !denied.contains(x)).collect()
+}
+
+/// Unwrap the IPv4 addresses embedded in IPv4-mapped, 6to4, Teredo and
+/// NAT64 addresses
+pub fn unwrap_embedded_ips(ips: Vec<IpAddr>, mode: UnwrapMode) -> Vec<IpAddr> {
+    if mode == UnwrapMode::Keep {
+        return ips;
+    }
+    let mut unwrapped = Vec::with_capacity(ips.len());
+    for ip in ips {
+        match ip {
+            IpAddr::V6(v6) => match embedded_ipv4(&v6) {
+                Some((_, v4)) => {
+                    if mode == UnwrapMode::Both {
+                        unwrapped.push(ip);
+                    }
+                    unwrapped.push(IpAddr::V4(v4));
+                }
+                None => unwrapped.push(ip),
+            },
+            IpAddr::V4(_) => unwrapped.push(ip),
+        }
+    }
+    unwrapped
+}
+
+/// Find every IPv4 and IPv6 address in the data, in document order
 pub fn get_all_ips(data: &str) -> Vec<IpAddr> {
-    let mut all_ips: Vec<IpAddr> = get_all_ipv4(data);
-    all_ips.append(&mut get_all_ipv6(data));
-    all_ips
+    get_all_ips_in_bytes(data.as_bytes())
+}
+
+/// Find every address in data that may not be valid UTF-8
+pub fn get_all_ips_in_bytes(data: &[u8]) -> Vec<IpAddr> {
+    get_all_ips_in_bytes_with(data, Ipv4Strictness::default())
+}
+
+pub fn get_all_ips_in_bytes_with(data: &[u8], strictness: Ipv4Strictness) -> Vec<IpAddr> {
+    scan_parallel(data, strictness, CHUNK_SIZE)
+}
+
+/// Find every address in the data with the port and IPv6 zone written
+/// alongside it, in document order
+pub fn get_all_endpoints(data: &[u8]) -> Vec<Endpoint> {
+    get_all_endpoints_with(data, Ipv4Strictness::default())
+}
+
+pub fn get_all_endpoints_with(data: &[u8], strictness: Ipv4Strictness) -> Vec<Endpoint> {
+    scan_parallel_with(data, strictness, CHUNK_SIZE, |found| found.endpoint(data))
 }
 
 pub fn get_all_ipv4(data: &str) -> Vec<IpAddr> {
-    lazy_static! {
-        static ref IPV4: Regex = match Regex::new(
-            r"(?:(?:(?:25[0-5]|(?:2[0-4]|1{0,1}[0-9]){0,1}[0-9])\.){3,3}(?:25[0-5]|(?:2[0-4]|1{0,1}[0-9]){0,1}[0-9]))"
-        ) {
-            Ok(x) => x,
-            Err(e) => panic!("Unanble to generate IPv4 pattern: {}", e),
-        };
-    }
+    get_all_ipv4_with(data, Ipv4Strictness::default())
+}
+
+lazy_static! {
+    static ref IPV4: Regex = match Regex::new(
+        r"(?:(?:(?:25[0-5]|(?:2[0-4]|1{0,1}[0-9]){0,1}[0-9])\.){3,3}(?:25[0-5]|(?:2[0-4]|1{0,1}[0-9]){0,1}[0-9]))"
+    ) {
+        Ok(x) => x,
+        Err(e) => panic!("Unanble to generate IPv4 pattern: {}", e),
+    };
+}
+
+pub fn get_all_ipv4_with(data: &str, strictness: Ipv4Strictness) -> Vec<IpAddr> {
     IPV4.find_iter(data)
+        .filter(|x| is_ipv4_boundary(data.as_bytes(), x.start(), x.end(), strictness))
         .map(|x| {
             let substr = x.as_str();
             substr.parse::<Ipv4Addr>().ok()
@@ -44,7 +139,30 @@ pub fn get_all_ipv4(data: &str) -> Vec<IpAddr> {
         .collect::<Vec<_>>()
 }
 
-fn get_all_ipv6(data: &str) -> Vec<IpAddr> {
+fn is_ipv4_boundary(data: &[u8], start: usize, end: usize, strictness: Ipv4Strictness) -> bool {
+    if strictness == Ipv4Strictness::Lenient {
+        return true;
+    }
+    let before = start.checked_sub(1).map(|i| data[i]);
+    let after_dots = data[end..].iter().position(|b| *b != b'.');
+    let after = data.get(end).copied();
+
+    let in_longer_run = matches!(before, Some(b'0'..=b'9' | b'.'))
+        || after_dots.is_some_and(|i| data[end + i].is_ascii_digit());
+    if in_longer_run {
+        return false;
+    }
+    if strictness == Ipv4Strictness::Standard {
+        return true;
+    }
+
+    let is_word =
+        |b: Option<u8>| b.is_some_and(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-');
+    let after_word_slash = before == Some(b'/') && is_word(start.checked_sub(2).map(|i| data[i]));
+    !is_word(before) && !is_word(after) && !after_word_slash
+}
+
+pub fn get_all_ipv6(data: &str) -> Vec<IpAddr> {
     lazy_static! {
         static ref IPV6: Regex = match Regex::new(
             r"(?:(?:(?:(?:[0-9a-fA-F]){1,4}):){1,4}:[^\s:](?:(?:(?:25[0-5]|(?:2[0-4]|1{0,1}[0-9]){0,1}[0-9])\.){3,3}(?:25[0-5]|(?:2[0-4]|1{0,1}[0-9]){0,1}[0-9])))|(?:::(?:ffff(?::0{1,4}){0,1}:){0,1}[^\s:](?:(?:(?:25[0-5]|(?:2[0-4]|1{0,1}[0-9]){0,1}[0-9])\.){3,3}(?:25[0-5]|(?:2[0-4]|1{0,1}[0-9]){0,1}[0-9])))|(?:fe80:(?::(?:(?:[0-9a-fA-F]){1,4})){0,4}%[0-9a-zA-Z]{1,})|(?::(?:(?::(?:(?:[0-9a-fA-F]){1,4})){1,7}|:))|(?:(?:(?:[0-9a-fA-F]){1,4}):(?:(?::(?:(?:[0-9a-fA-F]){1,4})){1,6}))|(?:(?:(?:(?:[0-9a-fA-F]){1,4}):){1,2}(?::(?:(?:[0-9a-fA-F]){1,4})){1,5})|(?:(?:(?:(?:[0-9a-fA-F]){1,4}):){1,3}(?::(?:(?:[0-9a-fA-F]){1,4})){1,4})|(?:(?:(?:(?:[0-9a-fA-F]){1,4}):){1,4}(?::(?:(?:[0-9a-fA-F]){1,4})){1,3})|(?:(?:(?:(?:[0-9a-fA-F]){1,4}):){1,5}(?::(?:(?:[0-9a-fA-F]){1,4})){1,2})|(?:(?:(?:(?:[0-9a-fA-F]){1,4}):){1,6}:(?:(?:[0-9a-fA-F]){1,4}))|(?:(?:(?:(?:[0-9a-fA-F]){1,4}):){1,7}:)|(?:(?:(?:(?:[0-9a-fA-F]){1,4}):){7,7}(?:(?:[0-9a-fA-F]){1,4}))"
@@ -56,7 +174,8 @@ fn get_all_ipv6(data: &str) -> Vec<IpAddr> {
 
     IPV6.find_iter(data)
         .map(|x| {
-            let substr = x.as_str();
+            // Drop the zone of a link-local address, such as fe80::1%eth0
+            let substr = x.as_str().split('%').next().unwrap_or_default();
             substr.parse::<Ipv6Addr>().ok()
         })
         .filter(|x| x.is_some())
@@ -67,6 +186,7 @@ fn get_all_ipv6(data: &str) -> Vec<IpAddr> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_get_routable_ips() {
@@ -86,6 +206,85 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_filter_special_purpose_ips() {
+        let ips = vec![
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            IpAddr::V4(Ipv4Addr::new(100, 64, 0, 1)),
+            IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)),
+            IpAddr::V6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1)),
+        ];
+
+        let actual = filter_special_purpose_ips(ips.clone(), &SpecialPurposeFilter::default());
+        assert_eq!(vec![IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))], actual);
+
+        let filter = SpecialPurposeFilter::default().allow(special_purpose::Category::Private);
+        let actual = filter_special_purpose_ips(ips, &filter);
+        assert_eq!(
+            vec![
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+                IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)),
+            ],
+            actual
+        );
+    }
+
+    #[test]
+    fn test_filter_allowed_and_denied_ips() {
+        let ips = vec![
+            IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7)),
+            IpAddr::V4(Ipv4Addr::new(203, 0, 113, 9)),
+            IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)),
+        ];
+        let prefixes: PrefixSet = vec!["198.51.100.0/24".parse().unwrap()]
+            .into_iter()
+            .collect();
+
+        assert_eq!(
+            vec![IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7))],
+            filter_allowed_ips(ips.clone(), &prefixes)
+        );
+        assert_eq!(
+            vec![
+                IpAddr::V4(Ipv4Addr::new(203, 0, 113, 9)),
+                IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)),
+            ],
+            filter_denied_ips(ips, &prefixes)
+        );
+    }
+
+    #[test]
+    fn test_unwrap_embedded_ips() {
+        let ips: Vec<IpAddr> = vec![
+            "::ffff:1.2.3.4".parse().unwrap(),
+            "2002:0808:0404::1".parse().unwrap(),
+            "2001:4860:4860::8888".parse().unwrap(),
+            "9.9.9.9".parse().unwrap(),
+        ];
+
+        assert_eq!(ips, unwrap_embedded_ips(ips.clone(), UnwrapMode::Keep));
+        assert_eq!(
+            vec![
+                IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)),
+                IpAddr::V4(Ipv4Addr::new(8, 8, 4, 4)),
+                ips[2],
+                ips[3],
+            ],
+            unwrap_embedded_ips(ips.clone(), UnwrapMode::Replace)
+        );
+        assert_eq!(
+            vec![
+                ips[0],
+                IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)),
+                ips[1],
+                IpAddr::V4(Ipv4Addr::new(8, 8, 4, 4)),
+                ips[2],
+                ips[3],
+            ],
+            unwrap_embedded_ips(ips.clone(), UnwrapMode::Both)
+        );
+    }
+
     #[test]
     fn test_get_distinct_ips() {
         let tests = vec![(
@@ -129,6 +328,83 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_get_all_ipv4_boundaries() {
+        let ip = |s: &str| IpAddr::V4(s.parse().unwrap());
+        let tests = vec![
+            ("1.1.1.1234", vec![], vec![]),
+            ("10.0.0.1.5", vec![], vec![]),
+            ("v1.2.3.4.5", vec![], vec![]),
+            ("Seen 8.8.8.8.", vec![ip("8.8.8.8")], vec![ip("8.8.8.8")]),
+            ("host8.8.8.8", vec![ip("8.8.8.8")], vec![]),
+            ("Chrome/120.0.0.1 Safari", vec![ip("120.0.0.1")], vec![]),
+            (
+                "http://1.2.3.4/ 10.0.0.0/8",
+                vec![ip("1.2.3.4"), ip("10.0.0.0")],
+                vec![ip("1.2.3.4"), ip("10.0.0.0")],
+            ),
+        ];
+        for (data, standard, strict) in tests {
+            assert_eq!(
+                standard,
+                get_all_ipv4_with(data, Ipv4Strictness::Standard),
+                "{}",
+                data
+            );
+            assert_eq!(
+                strict,
+                get_all_ipv4_with(data, Ipv4Strictness::Strict),
+                "{}",
+                data
+            );
+        }
+        assert_eq!(
+            vec![ip("1.1.1.123")],
+            get_all_ipv4_with("1.1.1.1234", Ipv4Strictness::Lenient)
+        );
+    }
+
+    /// Runs of digits and dots, without trailing dots, that parse as IPv4
+    fn reference_ipv4(data: &str) -> Vec<IpAddr> {
+        data.split(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .filter_map(|token| token.trim_end_matches('.').parse::<Ipv4Addr>().ok())
+            .map(IpAddr::V4)
+            .collect()
+    }
+
+    proptest! {
+        #[test]
+        fn prop_standard_matches_reference(data in "[0-9.a/ -]{0,40}") {
+            prop_assert_eq!(
+                reference_ipv4(&data),
+                get_all_ipv4_with(&data, Ipv4Strictness::Standard)
+            );
+        }
+
+        #[test]
+        fn prop_standard_matches_reference_near_addresses(
+            parts in proptest::collection::vec((0u16..300, "[.a /]{0,2}"), 1..12)
+        ) {
+            let data: String = parts
+                .iter()
+                .map(|(number, separator)| format!("{}{}", number, separator))
+                .collect();
+            prop_assert_eq!(
+                reference_ipv4(&data),
+                get_all_ipv4_with(&data, Ipv4Strictness::Standard)
+            );
+        }
+
+        #[test]
+        fn prop_strictness_narrows_matches(data in "[0-9.a_/:-]{0,40}") {
+            let lenient = get_all_ipv4_with(&data, Ipv4Strictness::Lenient);
+            let standard = get_all_ipv4_with(&data, Ipv4Strictness::Standard);
+            let strict = get_all_ipv4_with(&data, Ipv4Strictness::Strict);
+            prop_assert!(standard.iter().all(|ip| lenient.contains(ip)));
+            prop_assert!(strict.iter().all(|ip| standard.contains(ip)));
+        }
+    }
+
     #[test]
     fn test_get_all_ipv6() {
         let tests = vec![
@@ -147,6 +423,13 @@ mod test {
                     )),
                 ],
             ),
+            (
+                "[2001:db8::1]:443 fe80::1%eth0",
+                vec![
+                    IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+                    IpAddr::V6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1)),
+                ],
+            ),
         ];
         for test in tests {
             let actual = get_all_ipv6(test.0);
@@ -0,0 +1,208 @@
+use crate::util::cidr::Cidr;
+use anyhow::{anyhow, Result};
+use std::io::{BufRead, BufReader, Read};
+use std::net::IpAddr;
+use std::path::Path;
+
+#[derive(Default, Debug)]
+struct Node {
+    children: [Option<Box<Node>>; 2],
+    terminal: bool,
+}
+
+impl Node {
+    fn insert(&mut self, bits: u128, prefix_len: u8, width: u8) {
+        let mut node = self;
+        for depth in 0..prefix_len {
+            let bit = bit_at(bits, depth, width);
+            node = node.children[bit].get_or_insert_with(Box::default);
+        }
+        node.terminal = true;
+    }
+
+    fn covers(&self, bits: u128, width: u8) -> bool {
+        let mut node = self;
+        for depth in 0..width {
+            if node.terminal {
+                return true;
+            }
+            match &node.children[bit_at(bits, depth, width)] {
+                Some(child) => node = child,
+                None => return false,
+            }
+        }
+        node.terminal
+    }
+}
+
+fn bit_at(bits: u128, depth: u8, width: u8) -> usize {
+    ((bits >> (width - 1 - depth)) & 1) as usize
+}
+
+/// A set of network prefixes with lookups in a binary prefix trie, so
+/// matching an address costs at most one step per address bit regardless
+/// of the number of prefixes.
+#[derive(Default, Debug)]
+pub struct PrefixSet {
+    v4: Node,
+    v6: Node,
+    len: usize,
+}
+
+impl PrefixSet {
+    pub fn new() -> PrefixSet {
+        PrefixSet::default()
+    }
+
+    pub fn insert(&mut self, cidr: Cidr) {
+        match cidr.network() {
+            IpAddr::V4(v4) => self.v4.insert(u32::from(v4) as u128, cidr.prefix_len(), 32),
+            IpAddr::V6(v6) => self.v6.insert(u128::from(v6), cidr.prefix_len(), 128),
+        }
+        self.len += 1;
+    }
+
+    /// Whether any prefix in the set contains the address
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(v4) => self.v4.covers(u32::from(*v4) as u128, 32),
+            IpAddr::V6(v6) => self.v6.covers(u128::from(*v6), 128),
+        }
+    }
+
+    /// Number of prefixes added to the set
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Read one prefix per line. Blank lines and `#` comments, either on
+    /// their own line or after a prefix, are ignored.
+    pub fn from_reader<R: Read>(reader: R) -> Result<PrefixSet> {
+        let mut set = PrefixSet::new();
+        set.read_prefixes(reader)?;
+        Ok(set)
+    }
+
+    /// Load prefixes from one or more files, in the format of
+    /// [`PrefixSet::from_reader`]
+    pub fn from_files<P: AsRef<Path>>(file_paths: &[P]) -> Result<PrefixSet> {
+        let mut set = PrefixSet::new();
+        for file_path in file_paths {
+            let file_path = file_path.as_ref();
+            let file = std::fs::File::open(file_path);
+            let loaded = file
+                .map_err(|e| e.into())
+                .and_then(|f| set.read_prefixes(f));
+            loaded.map_err(|e| {
+                anyhow!(
+                    "Unable to load prefixes from {}: {}",
+                    file_path.display(),
+                    e
+                )
+            })?;
+        }
+        Ok(set)
+    }
+
+    fn read_prefixes<R: Read>(&mut self, reader: R) -> Result<()> {
+        for (index, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line?;
+            let prefix = line.split('#').next().unwrap_or("").trim();
+            if prefix.is_empty() {
+                continue;
+            }
+            let cidr = prefix
+                .parse::<Cidr>()
+                .map_err(|e| anyhow!("Line {}: {}", index + 1, e))?;
+            self.insert(cidr);
+        }
+        Ok(())
+    }
+}
+
+impl FromIterator<Cidr> for PrefixSet {
+    fn from_iter<I: IntoIterator<Item = Cidr>>(iter: I) -> Self {
+        let mut set = PrefixSet::new();
+        for cidr in iter {
+            set.insert(cidr);
+        }
+        set
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn set(prefixes: &[&str]) -> PrefixSet {
+        prefixes
+            .iter()
+            .map(|p| p.parse::<Cidr>().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_contains() {
+        let prefixes = set(&[
+            "10.0.0.0/8",
+            "192.0.2.128/25",
+            "203.0.113.7",
+            "2001:db8::/32",
+        ]);
+        assert_eq!(4, prefixes.len());
+
+        assert!(prefixes.contains(&ip("10.255.0.1")));
+        assert!(prefixes.contains(&ip("192.0.2.200")));
+        assert!(!prefixes.contains(&ip("192.0.2.100")));
+        assert!(prefixes.contains(&ip("203.0.113.7")));
+        assert!(!prefixes.contains(&ip("203.0.113.8")));
+        assert!(prefixes.contains(&ip("2001:db8:1::1")));
+        assert!(!prefixes.contains(&ip("2001:db9::1")));
+        assert!(!prefixes.contains(&ip("8.8.8.8")));
+    }
+
+    #[test]
+    fn test_contains_default_route() {
+        let prefixes = set(&["0.0.0.0/0"]);
+        assert!(prefixes.contains(&ip("8.8.8.8")));
+        assert!(!prefixes.contains(&ip("::1")));
+        assert!(!PrefixSet::new().contains(&ip("8.8.8.8")));
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let input = "# Egress ranges\n198.51.100.0/24\n\n2001:db8::/32  # partner\n   \n";
+        let prefixes = PrefixSet::from_reader(input.as_bytes()).unwrap();
+        assert_eq!(2, prefixes.len());
+        assert!(prefixes.contains(&ip("198.51.100.1")));
+        assert!(prefixes.contains(&ip("2001:db8::1")));
+
+        let error = PrefixSet::from_reader("10.0.0.0/8\nnot-a-prefix\n".as_bytes());
+        assert!(error.unwrap_err().to_string().starts_with("Line 2:"));
+    }
+
+    #[test]
+    fn test_from_files() {
+        let mut egress = NamedTempFile::new().unwrap();
+        writeln!(egress, "10.0.0.0/8").unwrap();
+        let mut partners = NamedTempFile::new().unwrap();
+        writeln!(partners, "# Partner\n2001:db8::/32").unwrap();
+
+        let prefixes = PrefixSet::from_files(&[egress.path(), partners.path()]).unwrap();
+        assert_eq!(2, prefixes.len());
+        assert!(prefixes.contains(&ip("10.1.1.1")));
+        assert!(prefixes.contains(&ip("2001:db8::1")));
+
+        assert!(PrefixSet::from_files(&[Path::new("not-a-file.txt")]).is_err());
+    }
+}
@@ -0,0 +1,419 @@
+use crate::util::{is_ipv4_boundary, Ipv4Strictness, IPV4};
+use rayon::prelude::*;
+use std::collections::VecDeque;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::ops::Range;
+use std::str::from_utf8;
+
+/// An address found by the [`Scanner`], with its byte range in the data
+#[derive(Debug, PartialEq, Eq)]
+pub struct Found {
+    pub ip: IpAddr,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// An address with the port and IPv6 zone written alongside it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Endpoint {
+    pub ip: IpAddr,
+    pub port: Option<u16>,
+    pub zone: Option<String>,
+}
+
+impl Found {
+    /// The port and zone around the address in the data, as in
+    /// `1.2.3.4:8080`, `[2001:db8::1]:443` or `fe80::1%eth0`. Ports are only
+    /// read after IPv4 and bracketed IPv6 addresses, as a port after a bare
+    /// IPv6 address would be part of it.
+    pub fn endpoint(&self, data: &[u8]) -> Endpoint {
+        let mut end = self.end;
+        let mut zone = None;
+        if self.ip.is_ipv6() && data.get(end) == Some(&b'%') {
+            let length = data[end + 1..]
+                .iter()
+                .take_while(|b| b.is_ascii_alphanumeric() || matches!(b, b'_' | b'-' | b'.'))
+                .count();
+            let name = &data[end + 1..end + 1 + length];
+            let name = &name[..name.len() - name.iter().rev().take_while(|b| **b == b'.').count()];
+            if !name.is_empty() {
+                zone = from_utf8(name).ok().map(String::from);
+                end += 1 + name.len();
+            }
+        }
+
+        let bracketed =
+            self.start > 0 && data[self.start - 1] == b'[' && data.get(end) == Some(&b']');
+        if bracketed {
+            end += 1;
+        }
+        let mut port = None;
+        if (bracketed || self.ip.is_ipv4()) && data.get(end) == Some(&b':') {
+            let digits = data[end + 1..]
+                .iter()
+                .take_while(|b| b.is_ascii_digit())
+                .count();
+            if (1..=5).contains(&digits) {
+                port = from_utf8(&data[end + 1..end + 1 + digits])
+                    .ok()
+                    .and_then(|p| p.parse::<u16>().ok());
+            }
+        }
+        Endpoint {
+            ip: self.ip,
+            port,
+            zone,
+        }
+    }
+}
+
+/// Finds IPv4 and IPv6 addresses in a single pass over the data, in
+/// document order.
+///
+/// The data is split into runs of hex digits, colons and dots. A run with a
+/// colon is parsed as an IPv6 address, ignoring a stray leading or trailing
+/// colon and trailing dots. Otherwise, each run of digits and dots within
+/// it is checked as an IPv4 address with the given strictness.
+pub struct Scanner<'a> {
+    data: &'a [u8],
+    position: usize,
+    limit: usize,
+    strictness: Ipv4Strictness,
+    pending: VecDeque<Found>,
+}
+
+impl<'a> Scanner<'a> {
+    pub fn new(data: &'a [u8], strictness: Ipv4Strictness) -> Scanner<'a> {
+        Scanner::with_range(data, strictness, 0..data.len())
+    }
+
+    /// Scan the addresses starting within the range, checking boundaries
+    /// against the surrounding data
+    pub fn with_range(
+        data: &'a [u8],
+        strictness: Ipv4Strictness,
+        range: Range<usize>,
+    ) -> Scanner<'a> {
+        Scanner {
+            data,
+            position: range.start,
+            limit: range.end.min(data.len()),
+            strictness,
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn scan_run(&mut self, start: usize, end: usize) {
+        let colons = self.data[start..end].iter().filter(|b| **b == b':').count();
+        if colons >= 2 {
+            if let Some(found) = self.ipv6_in_run(start, end) {
+                self.pending.push_back(found);
+                return;
+            }
+        }
+        self.ipv4_in_run(start, end);
+    }
+
+    fn ipv6_in_run(&self, mut start: usize, mut end: usize) -> Option<Found> {
+        let data = self.data;
+        while end > start && data[end - 1] == b'.' {
+            end -= 1;
+        }
+        if end - start > 1 && data[end - 1] == b':' && data[end - 2] != b':' {
+            end -= 1;
+        }
+        if end - start > 1 && data[start] == b':' && data[start + 1] != b':' {
+            start += 1;
+        }
+        let ip = from_utf8(&data[start..end])
+            .ok()?
+            .parse::<Ipv6Addr>()
+            .ok()?;
+        Some(Found {
+            ip: IpAddr::V6(ip),
+            start,
+            end,
+        })
+    }
+
+    fn ipv4_in_run(&mut self, start: usize, end: usize) {
+        let data = self.data;
+        let mut position = start;
+        while position < end {
+            if !is_ipv4_byte(data[position]) {
+                position += 1;
+                continue;
+            }
+            let run_start = position;
+            while position < end && is_ipv4_byte(data[position]) {
+                position += 1;
+            }
+            let mut run_end = position;
+            while run_end > run_start && data[run_end - 1] == b'.' {
+                run_end -= 1;
+            }
+
+            // Too short or too long for an address, or a lone number
+            let length = run_end - run_start;
+            if self.strictness != Ipv4Strictness::Lenient && !(7..=15).contains(&length) {
+                continue;
+            }
+
+            // The run only holds ASCII digits and dots
+            let run = from_utf8(&data[run_start..run_end]).unwrap_or_default();
+            match run.parse::<Ipv4Addr>() {
+                Ok(ip) if is_ipv4_boundary(data, run_start, run_end, self.strictness) => {
+                    self.pending.push_back(Found {
+                        ip: IpAddr::V4(ip),
+                        start: run_start,
+                        end: run_end,
+                    });
+                }
+                Err(_) if self.strictness == Ipv4Strictness::Lenient => {
+                    for m in IPV4.find_iter(run) {
+                        if let Ok(ip) = m.as_str().parse::<Ipv4Addr>() {
+                            self.pending.push_back(Found {
+                                ip: IpAddr::V4(ip),
+                                start: run_start + m.start(),
+                                end: run_start + m.end(),
+                            });
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Scanner<'a> {
+    type Item = Found;
+
+    fn next(&mut self) -> Option<Found> {
+        loop {
+            if let Some(found) = self.pending.pop_front() {
+                return Some(found);
+            }
+            let data = self.data;
+            let start = self.position
+                + data[self.position..self.limit]
+                    .iter()
+                    .position(|b| is_run_byte(*b))?;
+            let end = data[start..]
+                .iter()
+                .position(|b| !is_run_byte(*b))
+                .map_or(data.len(), |length| start + length);
+            self.position = end;
+            self.scan_run(start, end);
+        }
+    }
+}
+
+/// Inputs larger than this are split into chunks scanned in parallel
+pub const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Find every address in the data, scanning chunks of about `chunk_size`
+/// bytes in parallel. Chunks end on a byte that cannot be part of an
+/// address, so no address is split between chunks, and the addresses are
+/// returned in document order.
+pub fn scan_parallel(data: &[u8], strictness: Ipv4Strictness, chunk_size: usize) -> Vec<IpAddr> {
+    scan_parallel_with(data, strictness, chunk_size, |found| found.ip)
+}
+
+/// Like [`scan_parallel`], mapping each address found, such as to its
+/// [`Endpoint`]
+pub fn scan_parallel_with<T: Send>(
+    data: &[u8],
+    strictness: Ipv4Strictness,
+    chunk_size: usize,
+    map: impl Fn(Found) -> T + Sync,
+) -> Vec<T> {
+    chunk_ranges(data, chunk_size)
+        .into_par_iter()
+        .map(|range| {
+            Scanner::with_range(data, strictness, range)
+                .map(&map)
+                .collect::<Vec<T>>()
+        })
+        .flatten()
+        .collect()
+}
+
+fn chunk_ranges(data: &[u8], chunk_size: usize) -> Vec<Range<usize>> {
+    let mut ranges = vec![];
+    let mut start = 0;
+    while start < data.len() {
+        let mut end = (start + chunk_size.max(1)).min(data.len());
+        while end < data.len() && is_run_byte(data[end]) {
+            end += 1;
+        }
+        ranges.push(start..end);
+        start = end;
+    }
+    ranges
+}
+
+const RUN_BYTE: u8 = 1;
+const IPV4_BYTE: u8 = 2;
+
+// Byte classes, looked up once per byte of input
+const CLASSES: [u8; 256] = {
+    let mut classes = [0; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        let b = byte as u8;
+        if b.is_ascii_digit() || b == b'.' {
+            classes[byte] = RUN_BYTE | IPV4_BYTE;
+        } else if b.is_ascii_hexdigit() || b == b':' {
+            classes[byte] = RUN_BYTE;
+        }
+        byte += 1;
+    }
+    classes
+};
+
+fn is_run_byte(byte: u8) -> bool {
+    CLASSES[byte as usize] & RUN_BYTE != 0
+}
+
+fn is_ipv4_byte(byte: u8) -> bool {
+    CLASSES[byte as usize] & IPV4_BYTE != 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::get_all_ipv4_with;
+    use proptest::prelude::*;
+
+    fn scan(data: &str) -> Vec<String> {
+        Scanner::new(data.as_bytes(), Ipv4Strictness::Standard)
+            .map(|found| found.ip.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_document_order() {
+        assert_eq!(
+            vec!["2001:db8::1", "1.1.1.1", "2606:4700:4700::1111", "8.8.8.8"],
+            scan("2001:db8::1 1.1.1.1,2606:4700:4700::1111\t8.8.8.8")
+        );
+    }
+
+    #[test]
+    fn test_run_boundaries() {
+        let tests = vec![
+            ("host:2001:db8::1: up", vec!["2001:db8::1"]),
+            ("Seen ::ffff:1.2.3.4.", vec!["::ffff:1.2.3.4"]),
+            (
+                "10.0.0.1:8080 -> 10.0.0.2:443",
+                vec!["10.0.0.1", "10.0.0.2"],
+            ),
+            ("some127.0.0.1data8.8.8.8here", vec!["127.0.0.1", "8.8.8.8"]),
+            ("fe80::1%eth0", vec!["fe80::1"]),
+            ("at 12:30:45.123 version 1.2.3.4567", vec![]),
+        ];
+        for (data, expected) in tests {
+            assert_eq!(expected, scan(data), "{}", data);
+        }
+    }
+
+    #[test]
+    fn test_offsets() {
+        let data = "src=10.0.0.1 dst=[2001:db8::2]";
+        let found: Vec<&str> = Scanner::new(data.as_bytes(), Ipv4Strictness::Standard)
+            .map(|found| &data[found.start..found.end])
+            .collect();
+        assert_eq!(vec!["10.0.0.1", "2001:db8::2"], found);
+    }
+
+    #[test]
+    fn test_lenient() {
+        let found: Vec<IpAddr> = Scanner::new(b"1.1.1.1234 x", Ipv4Strictness::Lenient)
+            .map(|found| found.ip)
+            .collect();
+        assert_eq!(vec![IpAddr::V4(Ipv4Addr::new(1, 1, 1, 123))], found);
+    }
+
+    #[test]
+    fn test_endpoints() {
+        let data = "GET 1.2.3.4:8080 [2001:db8::1]:443 fe80::1%eth0. [fe80::2%en0]:22 \
+            2001:db8::5:80 [10.0.0.1]:25 9.9.9.9:99999 8.8.8.8: end";
+        let endpoints: Vec<(String, Option<u16>, Option<String>)> =
+            Scanner::new(data.as_bytes(), Ipv4Strictness::Standard)
+                .map(|found| {
+                    let endpoint = found.endpoint(data.as_bytes());
+                    (endpoint.ip.to_string(), endpoint.port, endpoint.zone)
+                })
+                .collect();
+        assert_eq!(
+            vec![
+                (String::from("1.2.3.4"), Some(8080), None),
+                (String::from("2001:db8::1"), Some(443), None),
+                (String::from("fe80::1"), None, Some(String::from("eth0"))),
+                (String::from("fe80::2"), Some(22), Some(String::from("en0"))),
+                (String::from("2001:db8::5:80"), None, None),
+                (String::from("10.0.0.1"), Some(25), None),
+                (String::from("9.9.9.9"), None, None),
+                (String::from("8.8.8.8"), None, None),
+            ],
+            endpoints
+        );
+    }
+
+    #[test]
+    fn test_chunk_ranges() {
+        let data = b"1.1.1.1 2.2.2.2 3.3.3.3";
+        assert_eq!(vec![0..7, 7..15, 15..23], chunk_ranges(data, 4));
+        assert_eq!(vec![0..23], chunk_ranges(data, 100));
+        assert!(chunk_ranges(b"", 4).is_empty());
+    }
+
+    #[test]
+    fn test_with_range() {
+        let data = b"Chrome/1.2.3.4 5.6.7.8";
+        let found: Vec<Found> = Scanner::with_range(data, Ipv4Strictness::Strict, 6..22).collect();
+        assert_eq!(1, found.len());
+        assert_eq!(15, found[0].start);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_parallel_matches_sequential(
+            data in "[0-9.a:g/ ]{0,200}",
+            chunk_size in 1usize..40
+        ) {
+            let sequential: Vec<IpAddr> = Scanner::new(data.as_bytes(), Ipv4Strictness::Strict)
+                .map(|found| found.ip)
+                .collect();
+            let parallel = scan_parallel(data.as_bytes(), Ipv4Strictness::Strict, chunk_size);
+            prop_assert_eq!(sequential, parallel);
+        }
+
+        #[test]
+        fn prop_ipv4_matches_regex(data in "[0-9.g_/ -]{0,40}") {
+            for strictness in [Ipv4Strictness::Standard, Ipv4Strictness::Strict] {
+                let scanned: Vec<IpAddr> = Scanner::new(data.as_bytes(), strictness)
+                    .map(|found| found.ip)
+                    .collect();
+                prop_assert_eq!(get_all_ipv4_with(&data, strictness), scanned);
+            }
+        }
+
+        #[test]
+        fn prop_recovers_joined_addresses(
+            ips in proptest::collection::vec(any::<IpAddr>(), 0..5),
+            separator in "[ ,;\t\n]"
+        ) {
+            let data = ips
+                .iter()
+                .map(|ip| ip.to_string())
+                .collect::<Vec<_>>()
+                .join(&separator);
+            let scanned: Vec<IpAddr> = Scanner::new(data.as_bytes(), Ipv4Strictness::Standard)
+                .map(|found| found.ip)
+                .collect();
+            prop_assert_eq!(ips, scanned);
+        }
+    }
+}
@@ -0,0 +1,326 @@
+use crate::util::cidr::Cidr;
+use lazy_static::lazy_static;
+use std::collections::HashSet;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Groups of special-purpose address blocks that can be filtered together
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Category {
+    Unspecified,
+    Loopback,
+    Private,
+    SharedAddressSpace,
+    LinkLocal,
+    UniqueLocal,
+    Documentation,
+    Benchmarking,
+    ProtocolAssignments,
+    Translation,
+    Discard,
+    Reserved,
+    Broadcast,
+    Multicast,
+}
+
+impl Category {
+    pub fn all() -> Vec<Category> {
+        vec![
+            Category::Unspecified,
+            Category::Loopback,
+            Category::Private,
+            Category::SharedAddressSpace,
+            Category::LinkLocal,
+            Category::UniqueLocal,
+            Category::Documentation,
+            Category::Benchmarking,
+            Category::ProtocolAssignments,
+            Category::Translation,
+            Category::Discard,
+            Category::Reserved,
+            Category::Broadcast,
+            Category::Multicast,
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Unspecified => "unspecified",
+            Category::Loopback => "loopback",
+            Category::Private => "private",
+            Category::SharedAddressSpace => "shared",
+            Category::LinkLocal => "link-local",
+            Category::UniqueLocal => "unique-local",
+            Category::Documentation => "documentation",
+            Category::Benchmarking => "benchmarking",
+            Category::ProtocolAssignments => "protocol",
+            Category::Translation => "translation",
+            Category::Discard => "discard",
+            Category::Reserved => "reserved",
+            Category::Broadcast => "broadcast",
+            Category::Multicast => "multicast",
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Category {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Category::all()
+            .into_iter()
+            .find(|category| category.name() == s.trim())
+            .ok_or_else(|| {
+                let names: Vec<&str> = Category::all().iter().map(|c| c.name()).collect();
+                format!(
+                    "Unknown special-purpose category {}, expected one of: {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// An entry of the IANA IPv4 and IPv6 Special-Purpose Address Registries
+/// (RFC 6890), extended with the multicast, reserved and broadcast blocks.
+#[derive(Debug, PartialEq)]
+pub struct SpecialPurposeBlock {
+    pub prefix: &'static str,
+    pub name: &'static str,
+    pub category: Category,
+    /// Whether the registry marks the block as globally reachable. Blocks
+    /// marked N/A, such as the transition prefixes, count as reachable.
+    pub globally_reachable: bool,
+}
+
+const fn block(
+    prefix: &'static str,
+    name: &'static str,
+    category: Category,
+    globally_reachable: bool,
+) -> SpecialPurposeBlock {
+    SpecialPurposeBlock {
+        prefix,
+        name,
+        category,
+        globally_reachable,
+    }
+}
+
+/// The special-purpose blocks, matched by most specific prefix
+#[rustfmt::skip]
+pub const REGISTRY: &[SpecialPurposeBlock] = &[
+    // IPv4
+    block("0.0.0.0/8", "This network", Category::Unspecified, false),
+    block("10.0.0.0/8", "Private-Use", Category::Private, false),
+    block("100.64.0.0/10", "Shared Address Space", Category::SharedAddressSpace, false),
+    block("127.0.0.0/8", "Loopback", Category::Loopback, false),
+    block("169.254.0.0/16", "Link Local", Category::LinkLocal, false),
+    block("172.16.0.0/12", "Private-Use", Category::Private, false),
+    block("192.0.0.0/24", "IETF Protocol Assignments", Category::ProtocolAssignments, false),
+    block("192.0.0.9/32", "Port Control Protocol Anycast", Category::ProtocolAssignments, true),
+    block("192.0.0.10/32", "Traversal Using Relays around NAT Anycast", Category::ProtocolAssignments, true),
+    block("192.0.2.0/24", "Documentation (TEST-NET-1)", Category::Documentation, false),
+    block("192.31.196.0/24", "AS112-v4", Category::ProtocolAssignments, true),
+    block("192.52.193.0/24", "AMT", Category::ProtocolAssignments, true),
+    block("192.88.99.0/24", "Deprecated (6to4 Relay Anycast)", Category::Translation, true),
+    block("192.168.0.0/16", "Private-Use", Category::Private, false),
+    block("192.175.48.0/24", "Direct Delegation AS112 Service", Category::ProtocolAssignments, true),
+    block("198.18.0.0/15", "Benchmarking", Category::Benchmarking, false),
+    block("198.51.100.0/24", "Documentation (TEST-NET-2)", Category::Documentation, false),
+    block("203.0.113.0/24", "Documentation (TEST-NET-3)", Category::Documentation, false),
+    block("224.0.0.0/4", "Multicast", Category::Multicast, false),
+    block("240.0.0.0/4", "Reserved", Category::Reserved, false),
+    block("255.255.255.255/32", "Limited Broadcast", Category::Broadcast, false),
+    // IPv6
+    block("::/128", "Unspecified Address", Category::Unspecified, false),
+    block("::1/128", "Loopback Address", Category::Loopback, false),
+    block("::ffff:0:0/96", "IPv4-mapped Address", Category::Translation, false),
+    block("64:ff9b::/96", "IPv4-IPv6 Translat.", Category::Translation, true),
+    block("64:ff9b:1::/48", "IPv4-IPv6 Translat.", Category::Translation, false),
+    block("100::/64", "Discard-Only Address Block", Category::Discard, false),
+    block("2001::/23", "IETF Protocol Assignments", Category::ProtocolAssignments, false),
+    block("2001::/32", "TEREDO", Category::Translation, true),
+    block("2001:1::1/128", "Port Control Protocol Anycast", Category::ProtocolAssignments, true),
+    block("2001:1::2/128", "Traversal Using Relays around NAT Anycast", Category::ProtocolAssignments, true),
+    block("2001:1::3/128", "DNS-SD Service Registration Protocol Anycast", Category::ProtocolAssignments, true),
+    block("2001:2::/48", "Benchmarking", Category::Benchmarking, false),
+    block("2001:3::/32", "AMT", Category::ProtocolAssignments, true),
+    block("2001:4:112::/48", "AS112-v6", Category::ProtocolAssignments, true),
+    block("2001:10::/28", "Deprecated (previously ORCHID)", Category::ProtocolAssignments, false),
+    block("2001:20::/28", "ORCHIDv2", Category::ProtocolAssignments, true),
+    block("2001:30::/28", "Drone Remote ID Protocol Entity Tags (DETs) Prefix", Category::ProtocolAssignments, true),
+    block("2001:db8::/32", "Documentation", Category::Documentation, false),
+    block("2002::/16", "6to4", Category::Translation, true),
+    block("2620:4f:8000::/48", "Direct Delegation AS112 Service", Category::ProtocolAssignments, true),
+    block("3fff::/20", "Documentation", Category::Documentation, false),
+    block("5f00::/16", "Segment Routing (SRv6) SIDs", Category::ProtocolAssignments, false),
+    block("fc00::/7", "Unique-Local", Category::UniqueLocal, false),
+    block("fe80::/10", "Link-Local Unicast", Category::LinkLocal, false),
+    block("ff00::/8", "Multicast", Category::Multicast, false),
+];
+
+lazy_static! {
+    static ref PARSED_REGISTRY: Vec<(Cidr, &'static SpecialPurposeBlock)> = REGISTRY
+        .iter()
+        .map(|block| match block.prefix.parse::<Cidr>() {
+            Ok(cidr) => (cidr, block),
+            Err(e) => panic!("Invalid special-purpose prefix: {}", e),
+        })
+        .collect();
+}
+
+/// Find the most specific special-purpose block containing the address
+pub fn special_purpose(ip: &IpAddr) -> Option<&'static SpecialPurposeBlock> {
+    PARSED_REGISTRY
+        .iter()
+        .filter(|(cidr, _)| cidr.contains(ip))
+        .max_by_key(|(cidr, _)| cidr.prefix_len())
+        .map(|(_, block)| *block)
+}
+
+/// Selects which special-purpose categories are dropped before resolution.
+///
+/// An address is dropped when its most specific block belongs to an
+/// enabled category and is not globally reachable. All categories are
+/// enabled by default.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpecialPurposeFilter {
+    categories: HashSet<Category>,
+}
+
+impl Default for SpecialPurposeFilter {
+    fn default() -> Self {
+        Self {
+            categories: Category::all().into_iter().collect(),
+        }
+    }
+}
+
+impl SpecialPurposeFilter {
+    /// A filter that keeps every address
+    pub fn none() -> Self {
+        Self {
+            categories: HashSet::new(),
+        }
+    }
+
+    /// Keep addresses of the category
+    pub fn allow(mut self, category: Category) -> Self {
+        self.categories.remove(&category);
+        self
+    }
+
+    /// Drop addresses of the category
+    pub fn deny(mut self, category: Category) -> Self {
+        self.categories.insert(category);
+        self
+    }
+
+    pub fn is_excluded(&self, ip: &IpAddr) -> bool {
+        match special_purpose(ip) {
+            Some(block) => !block.globally_reachable && self.categories.contains(&block.category),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_special_purpose() {
+        let tests = vec![
+            ("10.1.2.3", Some("Private-Use")),
+            ("100.100.100.100", Some("Shared Address Space")),
+            ("192.0.0.9", Some("Port Control Protocol Anycast")),
+            ("192.0.0.11", Some("IETF Protocol Assignments")),
+            ("198.19.255.255", Some("Benchmarking")),
+            ("241.1.1.1", Some("Reserved")),
+            ("255.255.255.255", Some("Limited Broadcast")),
+            ("2001:0:4136:e378::1", Some("TEREDO")),
+            ("2001:1::1", Some("Port Control Protocol Anycast")),
+            ("fd00::1", Some("Unique-Local")),
+            ("::ffff:1.2.3.4", Some("IPv4-mapped Address")),
+            ("8.8.8.8", None),
+            ("2606:4700:4700::1111", None),
+        ];
+        for (address, expected) in tests {
+            let actual = special_purpose(&ip(address)).map(|block| block.name);
+            assert_eq!(expected, actual, "{}", address);
+        }
+    }
+
+    #[test]
+    fn test_filter_default() {
+        let filter = SpecialPurposeFilter::default();
+        for excluded in [
+            "0.1.2.3",
+            "10.0.0.1",
+            "100.64.0.1",
+            "127.0.0.1",
+            "169.254.1.1",
+            "172.16.0.1",
+            "192.0.2.1",
+            "192.168.1.1",
+            "198.18.0.1",
+            "198.51.100.1",
+            "203.0.113.1",
+            "224.0.0.1",
+            "240.0.0.1",
+            "255.255.255.255",
+            "::",
+            "::1",
+            "100::1",
+            "2001:2::1",
+            "2001:db8::1",
+            "3fff::1",
+            "fc00::1",
+            "fe80::1",
+            "ff02::1",
+        ] {
+            assert!(filter.is_excluded(&ip(excluded)), "{}", excluded);
+        }
+        for kept in [
+            "8.8.8.8",
+            "192.0.0.9",
+            "192.31.196.1",
+            "2001:4860:4860::8888",
+            "2002:c000:0204::1",
+            "64:ff9b::808:808",
+        ] {
+            assert!(!filter.is_excluded(&ip(kept)), "{}", kept);
+        }
+    }
+
+    #[test]
+    fn test_filter_toggles() {
+        let filter = SpecialPurposeFilter::default().allow(Category::Private);
+        assert!(!filter.is_excluded(&ip("10.0.0.1")));
+        assert!(filter.is_excluded(&ip("127.0.0.1")));
+
+        let filter = SpecialPurposeFilter::none().deny(Category::Loopback);
+        assert!(!filter.is_excluded(&ip("10.0.0.1")));
+        assert!(filter.is_excluded(&ip("::1")));
+    }
+
+    #[test]
+    fn test_category_from_str() {
+        for category in Category::all() {
+            assert_eq!(Ok(category), category.name().parse::<Category>());
+        }
+        assert!("bogus".parse::<Category>().is_err());
+    }
+}
@@ -0,0 +1,247 @@
+use crate::resolver::ip_api::{IpApiRecord, IpApiRecords, Resolver};
+use anyhow::Result;
+use arrow::array::{ArrayRef, BooleanArray, Float32Array, StringArray, UInt32Array};
+use arrow::datatypes::{Field, Schema};
+use arrow::ipc::writer::FileWriter;
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use std::io::Write;
+use std::sync::Arc;
+
+/// Build the typed Arrow schema for the selected columns.
+///
+/// Columns keep the names used by ip-api. Any other column is read as text
+/// from the record context, and every column is nullable as ip-api only
+/// returns the requested fields.
+pub fn schema(columns: Option<&[String]>) -> Schema {
+    let fields: Vec<Field> = selected_columns(columns)
+        .iter()
+        .map(|column| column_array(&[], column).0)
+        .collect();
+    Schema::new(fields)
+}
+
+/// Convert resolved records into an Arrow record batch with the selected
+/// columns
+pub fn to_record_batch(records: &IpApiRecords, columns: Option<&[String]>) -> Result<RecordBatch> {
+    let (fields, arrays): (Vec<Field>, Vec<ArrayRef>) = selected_columns(columns)
+        .iter()
+        .map(|column| column_array(&records.records, column))
+        .unzip();
+    Ok(RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays)?)
+}
+
+/// Write resolved records as a Parquet file
+pub fn write_parquet<W: Write + Send>(
+    records: &IpApiRecords,
+    columns: Option<&[String]>,
+    writer: W,
+) -> Result<()> {
+    let batch = to_record_batch(records, columns)?;
+    let mut parquet_writer = ArrowWriter::try_new(writer, batch.schema(), None)?;
+    parquet_writer.write(&batch)?;
+    parquet_writer.close()?;
+    Ok(())
+}
+
+/// Write resolved records as an Arrow IPC file
+pub fn write_arrow_ipc<W: Write>(
+    records: &IpApiRecords,
+    columns: Option<&[String]>,
+    writer: W,
+) -> Result<()> {
+    let batch = to_record_batch(records, columns)?;
+    let mut ipc_writer = FileWriter::try_new(writer, &batch.schema())?;
+    ipc_writer.write(&batch)?;
+    ipc_writer.finish()?;
+    Ok(())
+}
+
+fn selected_columns(columns: Option<&[String]>) -> Vec<String> {
+    match columns {
+        Some(columns) => columns.to_vec(),
+        None => Resolver::allowed_columns(),
+    }
+}
+
+fn string_field(column: &str) -> Option<fn(&IpApiRecord) -> &Option<String>> {
+    let field: fn(&IpApiRecord) -> &Option<String> = match column {
+        "query" => |r| &r.query,
+        "status" => |r| &r.status,
+        "continent" => |r| &r.continent,
+        "continentCode" => |r| &r.continent_code,
+        "country" => |r| &r.country,
+        "countryCode" => |r| &r.country_code,
+        "region" => |r| &r.region,
+        "regionName" => |r| &r.region_name,
+        "city" => |r| &r.city,
+        "district" => |r| &r.district,
+        "zip" => |r| &r.zip,
+        "timezone" => |r| &r.timezone,
+        "currency" => |r| &r.currency,
+        "isp" => |r| &r.isp,
+        "org" => |r| &r.org,
+        "as" => |r| &r.asn,
+        "asname" => |r| &r.asname,
+        _ => return None,
+    };
+    Some(field)
+}
+
+fn column_array(records: &[IpApiRecord], column: &str) -> (Field, ArrayRef) {
+    let array: ArrayRef = match column {
+        "lat" => Arc::new(Float32Array::from_iter(records.iter().map(|r| r.lat))),
+        "lon" => Arc::new(Float32Array::from_iter(records.iter().map(|r| r.lon))),
+        "offset" => Arc::new(UInt32Array::from_iter(records.iter().map(|r| r.offset))),
+        "mobile" => Arc::new(BooleanArray::from_iter(records.iter().map(|r| r.mobile))),
+        "proxy" => Arc::new(BooleanArray::from_iter(records.iter().map(|r| r.proxy))),
+        "hosting" => Arc::new(BooleanArray::from_iter(records.iter().map(|r| r.hosting))),
+        _ => match string_field(column) {
+            Some(field) => Arc::new(StringArray::from_iter(
+                records.iter().map(|r| field(r).as_deref()),
+            )),
+            None => Arc::new(StringArray::from_iter(
+                records.iter().map(|r| r.context.get(column)),
+            )),
+        },
+    };
+    let field = Field::new(column, array.data_type().clone(), true);
+    (field, array)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use arrow::array::Array;
+    use arrow::datatypes::DataType;
+    use arrow::ipc::reader::FileReader;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::io::Cursor;
+    use tempfile::NamedTempFile;
+
+    fn sample_records() -> IpApiRecords {
+        IpApiRecords {
+            records: vec![
+                IpApiRecord {
+                    query: Some(String::from("1.1.1.1")),
+                    country_code: Some(String::from("AU")),
+                    lat: Some(-27.4766),
+                    lon: Some(153.0166),
+                    offset: Some(36000),
+                    hosting: Some(true),
+                    mobile: Some(false),
+                    context: [(String::from("scope"), String::from("public"))].into(),
+                    ..Default::default()
+                },
+                IpApiRecord {
+                    query: Some(String::from("8.8.8.8")),
+                    ..Default::default()
+                },
+            ],
+        }
+    }
+
+    fn columns() -> Vec<String> {
+        vec![
+            String::from("query"),
+            String::from("countryCode"),
+            String::from("lat"),
+            String::from("offset"),
+            String::from("mobile"),
+            String::from("hosting"),
+            String::from("scope"),
+        ]
+    }
+
+    #[test]
+    fn test_schema() {
+        let schema = schema(Some(&columns()));
+        let types: Vec<(&str, &DataType)> = schema
+            .fields()
+            .iter()
+            .map(|f| (f.name().as_str(), f.data_type()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("query", &DataType::Utf8),
+                ("countryCode", &DataType::Utf8),
+                ("lat", &DataType::Float32),
+                ("offset", &DataType::UInt32),
+                ("mobile", &DataType::Boolean),
+                ("hosting", &DataType::Boolean),
+                ("scope", &DataType::Utf8),
+            ],
+            types
+        );
+        assert_eq!(23, super::schema(None).fields().len());
+    }
+
+    #[test]
+    fn test_to_record_batch() {
+        let batch = to_record_batch(&sample_records(), Some(&columns())).unwrap();
+        assert_eq!(2, batch.num_rows());
+
+        let lat = batch
+            .column_by_name("lat")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<Float32Array>()
+            .unwrap();
+        assert_eq!(-27.4766, lat.value(0));
+        assert!(lat.is_null(1));
+
+        let hosting = batch
+            .column_by_name("hosting")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<BooleanArray>()
+            .unwrap();
+        assert!(hosting.value(0));
+        assert!(hosting.is_null(1));
+
+        let scope = batch
+            .column_by_name("scope")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!("public", scope.value(0));
+        assert!(scope.is_null(1));
+    }
+
+    #[test]
+    fn test_write_parquet() {
+        let file = NamedTempFile::new().unwrap();
+        write_parquet(&sample_records(), Some(&columns()), file.reopen().unwrap()).unwrap();
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file.reopen().unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|b| b.unwrap()).collect();
+        assert_eq!(1, batches.len());
+        assert_eq!(
+            to_record_batch(&sample_records(), Some(&columns())).unwrap(),
+            batches[0]
+        );
+    }
+
+    #[test]
+    fn test_write_arrow_ipc() {
+        let mut buffer = Vec::new();
+        write_arrow_ipc(&sample_records(), None, &mut buffer).unwrap();
+
+        let reader = FileReader::try_new(Cursor::new(buffer), None).unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|b| b.unwrap()).collect();
+        assert_eq!(1, batches.len());
+        assert_eq!(2, batches[0].num_rows());
+        assert_eq!(
+            &DataType::UInt32,
+            batches[0]
+                .schema()
+                .field_with_name("offset")
+                .unwrap()
+                .data_type()
+        );
+    }
+}
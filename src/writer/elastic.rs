@@ -0,0 +1,334 @@
+use super::{parse_as, to_f64};
+use crate::resolver::ip_api::{IpApiRecord, IpApiRecords};
+use anyhow::Result;
+use serde_json::{json, Map, Value};
+use std::io::Write;
+
+pub struct ElasticOptions {
+    /// Index the documents are written to
+    pub index: String,
+    /// ECS field set the enrichment is nested under, such as `source` or
+    /// `destination`
+    pub field_set: String,
+}
+
+impl Default for ElasticOptions {
+    fn default() -> Self {
+        Self {
+            index: String::from("chickadee"),
+            field_set: String::from("source"),
+        }
+    }
+}
+
+/// Write resolved records in the Elasticsearch/OpenSearch `_bulk` format.
+///
+/// Each record is written as an `index` action line followed by the record
+/// as a document with ECS field names.
+pub fn write_bulk<W: Write>(
+    records: &IpApiRecords,
+    options: &ElasticOptions,
+    writer: &mut W,
+) -> Result<()> {
+    let timestamp = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+    let action = json!({ "index": { "_index": options.index } });
+    for record in &records.records {
+        serde_json::to_writer(&mut *writer, &action)?;
+        writeln!(writer)?;
+        serde_json::to_writer(&mut *writer, &to_document(record, options, &timestamp))?;
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// Write the index template with the mappings of the bulk documents
+pub fn write_index_template<W: Write>(options: &ElasticOptions, writer: &mut W) -> Result<()> {
+    serde_json::to_writer_pretty(&mut *writer, &index_template(options))?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+/// Convert a record into a document with ECS field names
+pub fn to_document(record: &IpApiRecord, options: &ElasticOptions, timestamp: &str) -> Value {
+    let mut geo = Map::new();
+    insert_str(&mut geo, "continent_name", &record.continent);
+    insert_str(&mut geo, "continent_code", &record.continent_code);
+    insert_str(&mut geo, "country_name", &record.country);
+    insert_str(&mut geo, "country_iso_code", &record.country_code);
+    insert_str(&mut geo, "region_name", &record.region_name);
+    if let (Some(country), Some(region)) = (&record.country_code, &record.region) {
+        if !country.is_empty() && !region.is_empty() {
+            geo.insert(
+                String::from("region_iso_code"),
+                json!(format!("{}-{}", country, region)),
+            );
+        }
+    }
+    insert_str(&mut geo, "city_name", &record.city);
+    insert_str(&mut geo, "postal_code", &record.zip);
+    insert_str(&mut geo, "timezone", &record.timezone);
+    if let (Some(lat), Some(lon)) = (record.lat, record.lon) {
+        geo.insert(
+            String::from("location"),
+            json!({ "lat": to_f64(lat), "lon": to_f64(lon) }),
+        );
+    }
+
+    let mut autonomous_system = Map::new();
+    if let Some((number, remainder)) = record.asn.as_deref().and_then(parse_as) {
+        autonomous_system.insert(String::from("number"), json!(number));
+        let name = record.asname.clone().or(remainder);
+        if let Some(name) = name.filter(|name| !name.is_empty()) {
+            autonomous_system.insert(String::from("organization"), json!({ "name": name }));
+        }
+    }
+
+    let mut field_set = Map::new();
+    if let Some(query) = &record.query {
+        field_set.insert(String::from("ip"), json!(query));
+    }
+    if !geo.is_empty() {
+        field_set.insert(String::from("geo"), Value::Object(geo));
+    }
+    if !autonomous_system.is_empty() {
+        field_set.insert(String::from("as"), Value::Object(autonomous_system));
+    }
+
+    // Fields without an ECS equivalent keep their ip-api names
+    let mut custom = Map::new();
+    insert_str(&mut custom, "status", &record.status);
+    insert_str(&mut custom, "district", &record.district);
+    insert_str(&mut custom, "currency", &record.currency);
+    insert_str(&mut custom, "isp", &record.isp);
+    insert_str(&mut custom, "org", &record.org);
+    if let Some(offset) = record.offset {
+        custom.insert(String::from("offset"), json!(offset));
+    }
+    for (name, flag) in [
+        ("mobile", record.mobile),
+        ("proxy", record.proxy),
+        ("hosting", record.hosting),
+    ] {
+        if let Some(flag) = flag {
+            custom.insert(String::from(name), json!(flag));
+        }
+    }
+
+    let mut document = Map::new();
+    document.insert(String::from("@timestamp"), json!(timestamp));
+    document.insert(options.field_set.clone(), Value::Object(field_set));
+    if !custom.is_empty() {
+        document.insert(String::from("chickadee"), Value::Object(custom));
+    }
+    Value::Object(document)
+}
+
+/// Build a composable index template matching the bulk documents, so that
+/// addresses are mapped as `ip` and coordinates as `geo_point`.
+pub fn index_template(options: &ElasticOptions) -> Value {
+    let keyword = json!({ "type": "keyword", "ignore_above": 1024 });
+    let boolean = json!({ "type": "boolean" });
+
+    let mut properties = Map::new();
+    properties.insert(String::from("@timestamp"), json!({ "type": "date" }));
+    properties.insert(
+        options.field_set.clone(),
+        json!({
+            "properties": {
+                "ip": { "type": "ip" },
+                "geo": {
+                    "properties": {
+                        "continent_name": keyword,
+                        "continent_code": keyword,
+                        "country_name": keyword,
+                        "country_iso_code": keyword,
+                        "region_name": keyword,
+                        "region_iso_code": keyword,
+                        "city_name": keyword,
+                        "postal_code": keyword,
+                        "timezone": keyword,
+                        "location": { "type": "geo_point" },
+                    }
+                },
+                "as": {
+                    "properties": {
+                        "number": { "type": "long" },
+                        "organization": {
+                            "properties": {
+                                "name": {
+                                    "type": "keyword",
+                                    "ignore_above": 1024,
+                                    "fields": { "text": { "type": "text" } },
+                                }
+                            }
+                        },
+                    }
+                },
+            }
+        }),
+    );
+    properties.insert(
+        String::from("chickadee"),
+        json!({
+            "properties": {
+                "status": keyword,
+                "district": keyword,
+                "currency": keyword,
+                "isp": keyword,
+                "org": keyword,
+                "offset": { "type": "long" },
+                "mobile": boolean,
+                "proxy": boolean,
+                "hosting": boolean,
+            }
+        }),
+    );
+
+    json!({
+        "index_patterns": [format!("{}*", options.index)],
+        "template": {
+            "mappings": {
+                "dynamic": true,
+                "properties": properties,
+            }
+        },
+        "_meta": { "description": "Mappings for chickadee enrichment documents" },
+    })
+}
+
+fn insert_str(map: &mut Map<String, Value>, key: &str, value: &Option<String>) {
+    if let Some(value) = value.as_ref().filter(|value| !value.is_empty()) {
+        map.insert(key.to_string(), json!(value));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample_record() -> IpApiRecord {
+        IpApiRecord {
+            query: Some(String::from("1.1.1.1")),
+            status: Some(String::from("success")),
+            continent: Some(String::from("Oceania")),
+            continent_code: Some(String::from("OC")),
+            country: Some(String::from("Australia")),
+            country_code: Some(String::from("AU")),
+            region: Some(String::from("QLD")),
+            region_name: Some(String::from("Queensland")),
+            city: Some(String::from("South Brisbane")),
+            zip: Some(String::from("4101")),
+            lat: Some(-27.4766),
+            lon: Some(153.0166),
+            timezone: Some(String::from("Australia/Brisbane")),
+            asn: Some(String::from("AS13335 Cloudflare, Inc.")),
+            asname: Some(String::from("CLOUDFLARENET")),
+            isp: Some(String::from("Cloudflare, Inc")),
+            hosting: Some(true),
+            proxy: Some(false),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_to_document() {
+        let document = to_document(
+            &sample_record(),
+            &ElasticOptions::default(),
+            "2023-05-01T00:00:00.000Z",
+        );
+        let expected = json!({
+            "@timestamp": "2023-05-01T00:00:00.000Z",
+            "source": {
+                "ip": "1.1.1.1",
+                "geo": {
+                    "continent_name": "Oceania",
+                    "continent_code": "OC",
+                    "country_name": "Australia",
+                    "country_iso_code": "AU",
+                    "region_name": "Queensland",
+                    "region_iso_code": "AU-QLD",
+                    "city_name": "South Brisbane",
+                    "postal_code": "4101",
+                    "timezone": "Australia/Brisbane",
+                    "location": { "lat": -27.4766, "lon": 153.0166 },
+                },
+                "as": {
+                    "number": 13335,
+                    "organization": { "name": "CLOUDFLARENET" },
+                },
+            },
+            "chickadee": {
+                "status": "success",
+                "isp": "Cloudflare, Inc",
+                "proxy": false,
+                "hosting": true,
+            },
+        });
+        assert_eq!(expected, document);
+    }
+
+    #[test]
+    fn test_to_document_field_set() {
+        let options = ElasticOptions {
+            field_set: String::from("destination"),
+            ..Default::default()
+        };
+        let record = IpApiRecord {
+            query: Some(String::from("8.8.8.8")),
+            ..Default::default()
+        };
+        let document = to_document(&record, &options, "2023-05-01T00:00:00.000Z");
+        assert_eq!(
+            json!({
+                "@timestamp": "2023-05-01T00:00:00.000Z",
+                "destination": { "ip": "8.8.8.8" },
+            }),
+            document
+        );
+    }
+
+    #[test]
+    fn test_write_bulk() {
+        let records = IpApiRecords {
+            records: vec![sample_record(), sample_record()],
+        };
+        let options = ElasticOptions {
+            index: String::from("enrichment"),
+            ..Default::default()
+        };
+        let mut buffer = Vec::new();
+        write_bulk(&records, &options, &mut buffer).unwrap();
+
+        let lines: Vec<Value> = String::from_utf8(buffer)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(4, lines.len());
+        assert_eq!(json!({ "index": { "_index": "enrichment" } }), lines[0]);
+        assert_eq!("AU", lines[1]["source"]["geo"]["country_iso_code"]);
+        assert_eq!(lines[0], lines[2]);
+    }
+
+    #[test]
+    fn test_index_template() {
+        let template = index_template(&ElasticOptions::default());
+        let properties = &template["template"]["mappings"]["properties"];
+
+        assert_eq!(json!(["chickadee*"]), template["index_patterns"]);
+        assert_eq!("ip", properties["source"]["properties"]["ip"]["type"]);
+        assert_eq!(
+            "geo_point",
+            properties["source"]["properties"]["geo"]["properties"]["location"]["type"]
+        );
+        assert_eq!(
+            "long",
+            properties["source"]["properties"]["as"]["properties"]["number"]["type"]
+        );
+        assert_eq!(
+            "boolean",
+            properties["chickadee"]["properties"]["proxy"]["type"]
+        );
+    }
+}
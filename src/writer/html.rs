@@ -0,0 +1,351 @@
+use crate::resolver::ip_api::{IpApiRecord, IpApiRecords, Resolver};
+use anyhow::Result;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::Write;
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 2em; color: #222; }
+h1 { font-size: 1.6em; }
+h2 { font-size: 1.2em; margin-top: 1.5em; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: left; }
+th { background: #f0f0f0; }
+#records th { cursor: pointer; user-select: none; }
+.summary { display: flex; flex-wrap: wrap; gap: 2em; }
+.flag { display: inline-block; padding: 0 6px; margin-right: 4px; border-radius: 3px; font-size: 0.85em; color: #fff; }
+.flag-proxy { background: #c0392b; }
+.flag-hosting { background: #d35400; }
+.flag-mobile { background: #2980b9; }
+tr.flagged { background: #fff4e5; }
+"#;
+
+/// Columns sorted as numbers in the records table
+const NUMERIC_COLUMNS: [&str; 3] = ["lat", "lon", "offset"];
+
+// Sort the records table by the clicked column: numerically for numeric
+// columns, by address for cells holding IP addresses, and as text otherwise
+const SCRIPT: &str = r#"
+function ipKey(s) {
+  var v4 = /^(\d{1,3})\.(\d{1,3})\.(\d{1,3})\.(\d{1,3})$/.exec(s);
+  if (v4) {
+    return '4' + v4.slice(1).map(function (o) { return ('00' + o).slice(-3); }).join('');
+  }
+  if (s.indexOf(':') < 0 || !/^[0-9a-f:]+$/i.test(s)) { return null; }
+  var halves = s.toLowerCase().split('::');
+  if (halves.length > 2) { return null; }
+  var head = halves[0] ? halves[0].split(':') : [];
+  var tail = halves.length === 2 && halves[1] ? halves[1].split(':') : [];
+  var fill = 8 - head.length - tail.length;
+  if (fill < 0 || (halves.length === 1 && fill !== 0)) { return null; }
+  var groups = head.concat(new Array(halves.length === 2 ? fill : 0).fill('0'), tail);
+  return '6' + groups.map(function (g) { return ('000' + g).slice(-4); }).join('');
+}
+document.querySelectorAll('#records th').forEach(function (th, index) {
+  th.addEventListener('click', function () {
+    var body = document.querySelector('#records tbody');
+    var rows = Array.from(body.rows);
+    var ascending = th.dataset.order !== 'asc';
+    th.dataset.order = ascending ? 'asc' : 'desc';
+    rows.sort(function (a, b) {
+      var x = a.cells[index].textContent, y = b.cells[index].textContent;
+      var cmp;
+      if (th.dataset.sort === 'number') {
+        var nx = parseFloat(x), ny = parseFloat(y);
+        cmp = isNaN(nx) ? (isNaN(ny) ? 0 : 1) : (isNaN(ny) ? -1 : nx - ny);
+      } else {
+        var kx = ipKey(x), ky = ipKey(y);
+        cmp = (kx !== null && ky !== null) ? (kx < ky ? -1 : kx > ky ? 1 : 0) : x.localeCompare(y);
+      }
+      return ascending ? cmp : -cmp;
+    });
+    rows.forEach(function (row) { body.appendChild(row); });
+  });
+});
+"#;
+
+/// Write a standalone HTML report of the resolved records.
+///
+/// The report contains the list of sources, summary counts by country, ASN
+/// and organisation, and a sortable table of the records. All styling and
+/// scripting is inlined so the file can be viewed offline.
+pub fn write_html<W: Write>(
+    records: &IpApiRecords,
+    columns: Option<&[String]>,
+    sources: &[String],
+    writer: &mut W,
+) -> Result<()> {
+    let columns = match columns {
+        Some(columns) => columns.to_vec(),
+        None => Resolver::allowed_columns(),
+    };
+
+    writeln!(writer, "<!DOCTYPE html>")?;
+    writeln!(writer, "<html lang=\"en\">")?;
+    writeln!(writer, "<head>")?;
+    writeln!(writer, "<meta charset=\"utf-8\">")?;
+    writeln!(writer, "<title>Chickadee report</title>")?;
+    writeln!(writer, "<style>{}</style>", STYLE)?;
+    writeln!(writer, "</head>")?;
+    writeln!(writer, "<body>")?;
+    writeln!(writer, "<h1>Chickadee report</h1>")?;
+    writeln!(writer, "<p>{} records</p>", records.records.len())?;
+
+    write_sources(sources, writer)?;
+    write_summary(records, writer)?;
+    write_records(records, &columns, writer)?;
+
+    writeln!(writer, "<script>{}</script>", SCRIPT)?;
+    writeln!(writer, "</body>")?;
+    writeln!(writer, "</html>")?;
+    Ok(())
+}
+
+fn write_sources<W: Write>(sources: &[String], writer: &mut W) -> Result<()> {
+    writeln!(writer, "<h2>Sources</h2>")?;
+    if sources.is_empty() {
+        writeln!(writer, "<p>Command line input</p>")?;
+        return Ok(());
+    }
+    writeln!(writer, "<ul>")?;
+    for source in sources {
+        writeln!(writer, "<li>{}</li>", escape(source))?;
+    }
+    writeln!(writer, "</ul>")?;
+    Ok(())
+}
+
+fn write_summary<W: Write>(records: &IpApiRecords, writer: &mut W) -> Result<()> {
+    writeln!(writer, "<h2>Summary</h2>")?;
+    writeln!(writer, "<div class=\"summary\">")?;
+    write_counts("Country", count_by(records, |r| r.country.as_ref()), writer)?;
+    write_counts("ASN", count_by(records, |r| r.asn.as_ref()), writer)?;
+    write_counts(
+        "Organisation",
+        count_by(records, |r| r.org.as_ref()),
+        writer,
+    )?;
+
+    let flags = ["proxy", "hosting", "mobile"]
+        .iter()
+        .map(|flag| {
+            let count = records
+                .records
+                .iter()
+                .filter(|r| flags(r).contains(flag))
+                .count();
+            (flag.to_string(), count)
+        })
+        .collect();
+    write_counts("Flag", flags, writer)?;
+    writeln!(writer, "</div>")?;
+    Ok(())
+}
+
+fn write_counts<W: Write>(title: &str, counts: Vec<(String, usize)>, writer: &mut W) -> Result<()> {
+    writeln!(writer, "<table>")?;
+    writeln!(
+        writer,
+        "<thead><tr><th>{}</th><th>Count</th></tr></thead>",
+        escape(title)
+    )?;
+    writeln!(writer, "<tbody>")?;
+    for (name, count) in counts {
+        writeln!(
+            writer,
+            "<tr><td>{}</td><td>{}</td></tr>",
+            escape(&name),
+            count
+        )?;
+    }
+    writeln!(writer, "</tbody>")?;
+    writeln!(writer, "</table>")?;
+    Ok(())
+}
+
+fn write_records<W: Write>(
+    records: &IpApiRecords,
+    columns: &[String],
+    writer: &mut W,
+) -> Result<()> {
+    writeln!(writer, "<h2>Records</h2>")?;
+    writeln!(writer, "<table id=\"records\">")?;
+    write!(writer, "<thead><tr>")?;
+    for column in columns {
+        if NUMERIC_COLUMNS.contains(&column.as_str()) {
+            write!(writer, "<th data-sort=\"number\">{}</th>", escape(column))?;
+        } else {
+            write!(writer, "<th>{}</th>", escape(column))?;
+        }
+    }
+    writeln!(writer, "<th>flags</th></tr></thead>")?;
+    writeln!(writer, "<tbody>")?;
+    for record in &records.records {
+        let flags = flags(record);
+        if flags.is_empty() {
+            write!(writer, "<tr>")?;
+        } else {
+            write!(writer, "<tr class=\"flagged\">")?;
+        }
+
+        let value = serde_json::to_value(record)?;
+        for column in columns {
+            write!(writer, "<td>{}</td>", escape(&cell(&value, column)))?;
+        }
+
+        write!(writer, "<td>")?;
+        for flag in flags {
+            write!(writer, "<span class=\"flag flag-{0}\">{0}</span>", flag)?;
+        }
+        writeln!(writer, "</td></tr>")?;
+    }
+    writeln!(writer, "</tbody>")?;
+    writeln!(writer, "</table>")?;
+    Ok(())
+}
+
+/// Count the records sharing a value, most common first
+fn count_by<F>(records: &IpApiRecords, field: F) -> Vec<(String, usize)>
+where
+    F: Fn(&IpApiRecord) -> Option<&String>,
+{
+    let mut counts: HashMap<&String, usize> = HashMap::new();
+    for value in records.records.iter().filter_map(field) {
+        *counts.entry(value).or_insert(0) += 1;
+    }
+    let mut counts: Vec<(String, usize)> = counts
+        .into_iter()
+        .map(|(value, count)| (value.to_string(), count))
+        .collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts
+}
+
+fn flags(record: &IpApiRecord) -> Vec<&'static str> {
+    let mut flags = vec![];
+    if record.proxy == Some(true) {
+        flags.push("proxy");
+    }
+    if record.hosting == Some(true) {
+        flags.push("hosting");
+    }
+    if record.mobile == Some(true) {
+        flags.push("mobile");
+    }
+    flags
+}
+
+fn cell(record: &Value, column: &str) -> String {
+    match record.get(column) {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(s)) => s.to_string(),
+        Some(other) => other.to_string(),
+    }
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample_records() -> IpApiRecords {
+        IpApiRecords {
+            records: vec![
+                IpApiRecord {
+                    query: Some(String::from("1.1.1.1")),
+                    country: Some(String::from("Australia")),
+                    asn: Some(String::from("AS13335 Cloudflare, Inc.")),
+                    org: Some(String::from("APNIC and Cloudflare DNS Resolver project")),
+                    hosting: Some(true),
+                    ..Default::default()
+                },
+                IpApiRecord {
+                    query: Some(String::from("8.8.8.8")),
+                    country: Some(String::from("United States")),
+                    asn: Some(String::from("AS15169 Google LLC")),
+                    org: Some(String::from("Google Public DNS")),
+                    ..Default::default()
+                },
+                IpApiRecord {
+                    query: Some(String::from("8.8.4.4")),
+                    country: Some(String::from("United States")),
+                    asn: Some(String::from("AS15169 Google LLC")),
+                    org: Some(String::from("<script>")),
+                    proxy: Some(true),
+                    ..Default::default()
+                },
+            ],
+        }
+    }
+
+    fn render(records: &IpApiRecords, columns: Option<&[String]>, sources: &[String]) -> String {
+        let mut buffer = Vec::new();
+        write_html(records, columns, sources, &mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn test_write_html_summary() {
+        let html = render(&sample_records(), None, &[]);
+
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<tr><td>United States</td><td>2</td></tr>"));
+        assert!(html.contains("<tr><td>Australia</td><td>1</td></tr>"));
+        assert!(html.contains("<tr><td>AS15169 Google LLC</td><td>2</td></tr>"));
+        assert!(html.contains("<tr><td>proxy</td><td>1</td></tr>"));
+        assert!(html.contains("<tr><td>mobile</td><td>0</td></tr>"));
+        assert!(html.contains("<p>Command line input</p>"));
+        // Most common country is listed first
+        assert!(html.find("United States").unwrap() < html.find("Australia").unwrap());
+    }
+
+    #[test]
+    fn test_write_html_records() {
+        let columns = vec![String::from("query"), String::from("org")];
+        let sources = vec![String::from("auth.log"), String::from("access.log.gz")];
+        let html = render(&sample_records(), Some(&columns), &sources);
+
+        assert!(html.contains("<th>query</th><th>org</th><th>flags</th>"));
+        assert!(html.contains(
+            "<tr class=\"flagged\"><td>1.1.1.1</td><td>APNIC and Cloudflare DNS Resolver project</td><td><span class=\"flag flag-hosting\">hosting</span></td></tr>"
+        ));
+        assert!(html.contains("<tr><td>8.8.8.8</td><td>Google Public DNS</td><td></td></tr>"));
+        assert!(html.contains("<li>auth.log</li>"));
+        assert!(html.contains("<li>access.log.gz</li>"));
+        // No external resources are referenced
+        assert!(!html.contains("src=\"http"));
+        assert!(!html.contains("href=\"http"));
+    }
+
+    #[test]
+    fn test_write_html_numeric_columns() {
+        let columns = vec![
+            String::from("query"),
+            String::from("lat"),
+            String::from("as"),
+        ];
+        let html = render(&sample_records(), Some(&columns), &[]);
+        assert!(html.contains("<th>query</th><th data-sort=\"number\">lat</th><th>as</th>"));
+    }
+
+    #[test]
+    fn test_escape() {
+        let html = render(&sample_records(), None, &[String::from("a&b.log")]);
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(html.contains("<li>a&amp;b.log</li>"));
+    }
+}
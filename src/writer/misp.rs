@@ -0,0 +1,351 @@
+use super::parse_as;
+use crate::resolver::ip_api::{IpApiRecord, IpApiRecords};
+use anyhow::Result;
+use chrono::NaiveDate;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::io::Write;
+use std::net::IpAddr;
+use uuid::Uuid;
+
+/// Role of the addresses in the event, selecting the MISP attribute type
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MispRole {
+    Source,
+    Destination,
+}
+
+impl MispRole {
+    fn attribute_type(&self) -> &'static str {
+        match self {
+            MispRole::Source => "ip-src",
+            MispRole::Destination => "ip-dst",
+        }
+    }
+}
+
+/// Where the geolocation and ASN details of an address are recorded
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MispContext {
+    /// As the comment of the address attribute
+    Comment,
+    /// As `geolocation` and `asn` objects referencing the address attribute
+    Object,
+}
+
+pub struct MispOptions {
+    pub role: MispRole,
+    pub context: MispContext,
+    pub info: String,
+}
+
+impl Default for MispOptions {
+    fn default() -> Self {
+        Self {
+            role: MispRole::Source,
+            context: MispContext::Comment,
+            info: String::from("chickadee IP address enrichment"),
+        }
+    }
+}
+
+/// Convert resolved records into a MISP event.
+///
+/// Each distinct address becomes an `ip-src` or `ip-dst` attribute, tagged
+/// with `chickadee:flag` tags for the proxy, hosting and mobile flags.
+pub fn to_event(records: &IpApiRecords, options: &MispOptions) -> Value {
+    build_event(
+        records,
+        options,
+        Uuid::new_v4(),
+        chrono::Utc::now().date_naive(),
+    )
+}
+
+/// Write resolved records as a MISP event
+pub fn write_misp<W: Write>(
+    records: &IpApiRecords,
+    options: &MispOptions,
+    writer: &mut W,
+) -> Result<()> {
+    serde_json::to_writer_pretty(&mut *writer, &to_event(records, options))?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+fn build_event(
+    records: &IpApiRecords,
+    options: &MispOptions,
+    event_uuid: Uuid,
+    date: NaiveDate,
+) -> Value {
+    let mut attributes = vec![];
+    let mut objects = vec![];
+    let mut seen = HashSet::new();
+
+    for record in &records.records {
+        let ip_addr = match record.query.as_ref().and_then(|q| q.parse::<IpAddr>().ok()) {
+            Some(ip_addr) => ip_addr,
+            None => continue,
+        };
+        if !seen.insert(ip_addr) {
+            continue;
+        }
+
+        let attribute_type = options.role.attribute_type();
+        let value = ip_addr.to_string();
+        let attribute_uuid = child_uuid(&event_uuid, attribute_type, &value);
+
+        let mut attribute = json!({
+            "uuid": attribute_uuid.to_string(),
+            "type": attribute_type,
+            "category": "Network activity",
+            "value": value,
+            "to_ids": false,
+            "distribution": "5",
+            "comment": "",
+        });
+        let tags = tags(record);
+        if !tags.is_empty() {
+            attribute["Tag"] = Value::Array(tags);
+        }
+
+        match options.context {
+            MispContext::Comment => {
+                attribute["comment"] = Value::String(comment(record));
+            }
+            MispContext::Object => {
+                if let Some(object) = geolocation_object(record, &event_uuid, &attribute_uuid) {
+                    objects.push(object);
+                }
+                if let Some(object) = asn_object(record, &event_uuid, &attribute_uuid) {
+                    objects.push(object);
+                }
+            }
+        }
+        attributes.push(attribute);
+    }
+
+    let mut event = json!({
+        "uuid": event_uuid.to_string(),
+        "info": options.info,
+        "date": date.format("%Y-%m-%d").to_string(),
+        "threat_level_id": "4",
+        "analysis": "0",
+        "distribution": "0",
+        "published": false,
+        "Attribute": attributes,
+    });
+    if !objects.is_empty() {
+        event["Object"] = Value::Array(objects);
+    }
+    json!({ "Event": event })
+}
+
+/// Derive a stable identifier for an attribute or object of the event
+fn child_uuid(event_uuid: &Uuid, kind: &str, value: &str) -> Uuid {
+    Uuid::new_v5(event_uuid, format!("{}|{}", kind, value).as_bytes())
+}
+
+fn tags(record: &IpApiRecord) -> Vec<Value> {
+    [
+        ("proxy", record.proxy),
+        ("hosting", record.hosting),
+        ("mobile", record.mobile),
+    ]
+    .iter()
+    .filter(|(_, flag)| *flag == Some(true))
+    .map(|(name, _)| json!({ "name": format!("chickadee:flag=\"{}\"", name) }))
+    .collect()
+}
+
+fn comment(record: &IpApiRecord) -> String {
+    let location = [&record.city, &record.region_name, &record.country]
+        .iter()
+        .filter_map(|part| part.as_deref())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut parts = vec![];
+    if !location.is_empty() {
+        parts.push(format!("Location: {}", location));
+    }
+    if let (Some(lat), Some(lon)) = (record.lat, record.lon) {
+        parts.push(format!("Coordinates: {}, {}", lat, lon));
+    }
+    if let Some(asn) = &record.asn {
+        parts.push(format!("ASN: {}", asn));
+    }
+    if let Some(org) = &record.org {
+        parts.push(format!("Org: {}", org));
+    }
+    parts.join("; ")
+}
+
+fn object_attribute(relation: &str, attribute_type: &str, category: &str, value: Value) -> Value {
+    json!({
+        "object_relation": relation,
+        "type": attribute_type,
+        "category": category,
+        "value": value,
+        "to_ids": false,
+    })
+}
+
+fn geolocation_object(
+    record: &IpApiRecord,
+    event_uuid: &Uuid,
+    attribute_uuid: &Uuid,
+) -> Option<Value> {
+    let mut attributes = vec![];
+    let text_fields = [
+        ("country", &record.country),
+        ("countrycode", &record.country_code),
+        ("region", &record.region_name),
+        ("city", &record.city),
+        ("zipcode", &record.zip),
+    ];
+    for (relation, value) in text_fields {
+        if let Some(value) = value.as_ref().filter(|value| !value.is_empty()) {
+            attributes.push(object_attribute(relation, "text", "Other", json!(value)));
+        }
+    }
+    if let (Some(lat), Some(lon)) = (record.lat, record.lon) {
+        attributes.push(object_attribute(
+            "latitude",
+            "float",
+            "Other",
+            json!(lat.to_string()),
+        ));
+        attributes.push(object_attribute(
+            "longitude",
+            "float",
+            "Other",
+            json!(lon.to_string()),
+        ));
+    }
+    if attributes.is_empty() {
+        return None;
+    }
+
+    Some(json!({
+        "uuid": child_uuid(event_uuid, "geolocation", &attribute_uuid.to_string()).to_string(),
+        "name": "geolocation",
+        "meta-category": "misc",
+        "distribution": "5",
+        "Attribute": attributes,
+        "ObjectReference": [{
+            "referenced_uuid": attribute_uuid.to_string(),
+            "relationship_type": "characterizes",
+        }],
+    }))
+}
+
+fn asn_object(record: &IpApiRecord, event_uuid: &Uuid, attribute_uuid: &Uuid) -> Option<Value> {
+    let (number, remainder) = parse_as(record.asn.as_ref()?)?;
+
+    let mut attributes = vec![object_attribute(
+        "asn",
+        "AS",
+        "Network activity",
+        json!(format!("AS{}", number)),
+    )];
+    let description = record.asname.clone().or(remainder);
+    if let Some(description) = description.filter(|d| !d.is_empty()) {
+        attributes.push(object_attribute(
+            "description",
+            "text",
+            "Other",
+            json!(description),
+        ));
+    }
+
+    Some(json!({
+        "uuid": child_uuid(event_uuid, "asn", &attribute_uuid.to_string()).to_string(),
+        "name": "asn",
+        "meta-category": "network",
+        "distribution": "5",
+        "Attribute": attributes,
+        "ObjectReference": [{
+            "referenced_uuid": attribute_uuid.to_string(),
+            "relationship_type": "characterizes",
+        }],
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const EVENT_UUID: Uuid = Uuid::from_u128(0x5f0e2a36_3c4b_4f6e_9d1a_0c2b7e8f9a10);
+
+    fn sample_records() -> IpApiRecords {
+        IpApiRecords {
+            records: vec![
+                IpApiRecord {
+                    query: Some(String::from("1.1.1.1")),
+                    country: Some(String::from("Australia")),
+                    country_code: Some(String::from("AU")),
+                    region_name: Some(String::from("Queensland")),
+                    city: Some(String::from("South Brisbane")),
+                    lat: Some(-27.4766),
+                    lon: Some(153.0166),
+                    asn: Some(String::from("AS13335 Cloudflare, Inc.")),
+                    asname: Some(String::from("CLOUDFLARENET")),
+                    org: Some(String::from("APNIC and Cloudflare DNS Resolver project")),
+                    hosting: Some(true),
+                    ..Default::default()
+                },
+                IpApiRecord {
+                    query: Some(String::from("1.1.1.1")),
+                    ..Default::default()
+                },
+                IpApiRecord {
+                    query: Some(String::from("2a02:26f0::1")),
+                    country: Some(String::from("Germany")),
+                    asn: Some(String::from("AS20940 Akamai International B.V.")),
+                    proxy: Some(true),
+                    mobile: Some(true),
+                    ..Default::default()
+                },
+            ],
+        }
+    }
+
+    fn fixture(name: &str) -> Value {
+        let path = format!("test_data/misp/{}", name);
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    fn date() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2023, 5, 1).unwrap()
+    }
+
+    #[test]
+    fn test_build_event_comment() {
+        let options = MispOptions::default();
+        let actual = build_event(&sample_records(), &options, EVENT_UUID, date());
+        assert_eq!(fixture("event_comment.json"), actual);
+    }
+
+    #[test]
+    fn test_build_event_object() {
+        let options = MispOptions {
+            role: MispRole::Destination,
+            context: MispContext::Object,
+            info: String::from("Phishing infrastructure"),
+        };
+        let actual = build_event(&sample_records(), &options, EVENT_UUID, date());
+        assert_eq!(fixture("event_object.json"), actual);
+    }
+
+    #[test]
+    fn test_write_misp() {
+        let mut buffer = Vec::new();
+        write_misp(&sample_records(), &MispOptions::default(), &mut buffer).unwrap();
+        let event: Value = serde_json::from_slice(&buffer).unwrap();
+        assert_eq!(2, event["Event"]["Attribute"].as_array().unwrap().len());
+        assert_eq!("ip-src", event["Event"]["Attribute"][0]["type"]);
+    }
+}
@@ -1 +1,46 @@
+pub mod columnar;
+pub mod elastic;
+pub mod html;
+pub mod misp;
+pub mod stix;
 
+/// Split an ip-api `as` value, such as `AS13335 Cloudflare, Inc.`, into the
+/// AS number and the remaining name
+pub(crate) fn parse_as(value: &str) -> Option<(u32, Option<String>)> {
+    let value = value.trim();
+    let value = value.strip_prefix("AS").unwrap_or(value);
+    let digits = value.chars().take_while(|c| c.is_ascii_digit()).count();
+    let number = value[..digits].parse::<u32>().ok()?;
+    let remainder = value[digits..].trim();
+    if remainder.is_empty() {
+        Some((number, None))
+    } else {
+        Some((number, Some(remainder.to_string())))
+    }
+}
+
+/// Widen an f32 without exposing binary rounding noise in the output
+pub(crate) fn to_f64(value: f32) -> f64 {
+    value.to_string().parse::<f64>().unwrap_or(value as f64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_as() {
+        assert_eq!(
+            Some((13335, Some(String::from("Cloudflare, Inc.")))),
+            parse_as("AS13335 Cloudflare, Inc.")
+        );
+        assert_eq!(Some((15169, None)), parse_as("AS15169"));
+        assert_eq!(None, parse_as(""));
+    }
+
+    #[test]
+    fn test_to_f64() {
+        assert_eq!(-27.4766, to_f64(-27.4766));
+        assert_eq!(153.0166, to_f64(153.0166));
+    }
+}
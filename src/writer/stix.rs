@@ -0,0 +1,321 @@
+use super::{parse_as, to_f64};
+use crate::resolver::ip_api::{IpApiRecord, IpApiRecords};
+use anyhow::Result;
+use serde_json::{json, Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::net::IpAddr;
+use uuid::Uuid;
+
+/// Namespace for deterministic Cyber-observable Object identifiers, as
+/// defined in section 2.9 of the STIX 2.1 specification.
+const SCO_NAMESPACE: Uuid = Uuid::from_u128(0x00abedb4_aa42_466c_9c01_fed23315a9b7);
+
+const SPEC_VERSION: &str = "2.1";
+
+/// Convert resolved records into a STIX 2.1 bundle.
+///
+/// Each address becomes an `ipv4-addr` or `ipv6-addr` observable, linked by
+/// `belongs-to` relationships to an `autonomous-system` built from the `as`
+/// and `asname` fields, and by `located-at` relationships to a `location`
+/// built from the geo fields. Observables use the deterministic identifiers
+/// from the specification so repeated exports of the same address merge.
+pub fn to_bundle(records: &IpApiRecords) -> Value {
+    let timestamp = chrono::Utc::now()
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        .to_string();
+    let mut bundle = BundleBuilder::new(timestamp);
+    for record in &records.records {
+        bundle.add_record(record);
+    }
+    bundle.finish()
+}
+
+/// Write resolved records as a STIX 2.1 bundle
+pub fn write_stix<W: Write>(records: &IpApiRecords, writer: &mut W) -> Result<()> {
+    serde_json::to_writer_pretty(&mut *writer, &to_bundle(records))?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+struct BundleBuilder {
+    timestamp: String,
+    objects: Vec<Value>,
+    seen: HashSet<String>,
+    locations: HashMap<String, String>,
+}
+
+impl BundleBuilder {
+    fn new(timestamp: String) -> Self {
+        Self {
+            timestamp,
+            objects: vec![],
+            seen: HashSet::new(),
+            locations: HashMap::new(),
+        }
+    }
+
+    fn add_record(&mut self, record: &IpApiRecord) {
+        let ip_addr = match record.query.as_ref().and_then(|q| q.parse::<IpAddr>().ok()) {
+            Some(ip_addr) => ip_addr,
+            None => return,
+        };
+
+        let address_type = match ip_addr {
+            IpAddr::V4(_) => "ipv4-addr",
+            IpAddr::V6(_) => "ipv6-addr",
+        };
+        let value = ip_addr.to_string();
+        let address_id = observable_id(address_type, &json!({ "value": value }));
+        self.push(
+            &address_id,
+            json!({
+                "type": address_type,
+                "spec_version": SPEC_VERSION,
+                "id": address_id,
+                "value": value,
+            }),
+        );
+
+        if let Some(as_id) = self.add_autonomous_system(record) {
+            self.add_relationship("belongs-to", &address_id, &as_id);
+        }
+        if let Some(location_id) = self.add_location(record) {
+            self.add_relationship("located-at", &address_id, &location_id);
+        }
+    }
+
+    fn add_autonomous_system(&mut self, record: &IpApiRecord) -> Option<String> {
+        let (number, remainder) = parse_as(record.asn.as_ref()?)?;
+        let as_id = observable_id("autonomous-system", &json!({ "number": number }));
+
+        let mut object = json!({
+            "type": "autonomous-system",
+            "spec_version": SPEC_VERSION,
+            "id": as_id,
+            "number": number,
+        });
+        let name = record
+            .asname
+            .clone()
+            .or(remainder)
+            .filter(|name| !name.is_empty());
+        if let Some(name) = name {
+            object["name"] = Value::String(name);
+        }
+        self.push(&as_id, object);
+        Some(as_id)
+    }
+
+    fn add_location(&mut self, record: &IpApiRecord) -> Option<String> {
+        let mut properties = Map::new();
+        if let Some(country) = &record.country_code {
+            properties.insert(String::from("country"), json!(country));
+        }
+        if let Some(region) = &record.region_name {
+            properties.insert(String::from("administrative_area"), json!(region));
+        }
+        if let Some(city) = &record.city {
+            properties.insert(String::from("city"), json!(city));
+        }
+        if let Some(zip) = record.zip.as_ref().filter(|zip| !zip.is_empty()) {
+            properties.insert(String::from("postal_code"), json!(zip));
+        }
+        if let (Some(lat), Some(lon)) = (record.lat, record.lon) {
+            properties.insert(String::from("latitude"), json!(to_f64(lat)));
+            properties.insert(String::from("longitude"), json!(to_f64(lon)));
+        }
+        // A location must have at least a country or coordinates
+        if !properties.contains_key("country") && !properties.contains_key("latitude") {
+            return None;
+        }
+
+        let key = Value::Object(properties.clone()).to_string();
+        if let Some(location_id) = self.locations.get(&key) {
+            return Some(location_id.to_string());
+        }
+
+        let location_id = format!("location--{}", Uuid::new_v4());
+        let name = [&record.city, &record.region_name, &record.country]
+            .iter()
+            .filter_map(|part| part.as_deref())
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let mut object = json!({
+            "type": "location",
+            "spec_version": SPEC_VERSION,
+            "id": location_id,
+            "created": self.timestamp,
+            "modified": self.timestamp,
+        });
+        if !name.is_empty() {
+            object["name"] = Value::String(name);
+        }
+        if let Some(object) = object.as_object_mut() {
+            object.extend(properties);
+        }
+
+        self.locations.insert(key, location_id.clone());
+        self.push(&location_id, object);
+        Some(location_id)
+    }
+
+    fn add_relationship(&mut self, relationship_type: &str, source_ref: &str, target_ref: &str) {
+        let key = format!("{} {} {}", source_ref, relationship_type, target_ref);
+        if self.seen.contains(&key) {
+            return;
+        }
+        self.seen.insert(key);
+
+        let relationship_id = format!("relationship--{}", Uuid::new_v4());
+        self.push(
+            &relationship_id,
+            json!({
+                "type": "relationship",
+                "spec_version": SPEC_VERSION,
+                "id": relationship_id,
+                "created": self.timestamp,
+                "modified": self.timestamp,
+                "relationship_type": relationship_type,
+                "source_ref": source_ref,
+                "target_ref": target_ref,
+            }),
+        );
+    }
+
+    fn push(&mut self, id: &str, object: Value) {
+        if self.seen.insert(id.to_string()) {
+            self.objects.push(object);
+        }
+    }
+
+    fn finish(self) -> Value {
+        json!({
+            "type": "bundle",
+            "id": format!("bundle--{}", Uuid::new_v4()),
+            "objects": self.objects,
+        })
+    }
+}
+
+/// Build the deterministic identifier of an observable from its ID
+/// contributing properties
+fn observable_id(object_type: &str, contributing_properties: &Value) -> String {
+    let name = contributing_properties.to_string();
+    format!(
+        "{}--{}",
+        object_type,
+        Uuid::new_v5(&SCO_NAMESPACE, name.as_bytes())
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample_records() -> IpApiRecords {
+        IpApiRecords {
+            records: vec![
+                IpApiRecord {
+                    query: Some(String::from("1.1.1.1")),
+                    country: Some(String::from("Australia")),
+                    country_code: Some(String::from("AU")),
+                    region_name: Some(String::from("Queensland")),
+                    city: Some(String::from("South Brisbane")),
+                    zip: Some(String::from("4101")),
+                    lat: Some(-27.4766),
+                    lon: Some(153.0166),
+                    asn: Some(String::from("AS13335 Cloudflare, Inc.")),
+                    asname: Some(String::from("CLOUDFLARENET")),
+                    ..Default::default()
+                },
+                IpApiRecord {
+                    query: Some(String::from("2606:4700:4700::1111")),
+                    country_code: Some(String::from("US")),
+                    asn: Some(String::from("AS13335 Cloudflare, Inc.")),
+                    ..Default::default()
+                },
+                IpApiRecord {
+                    query: Some(String::from("1.1.1.1")),
+                    ..Default::default()
+                },
+            ],
+        }
+    }
+
+    fn objects_of_type<'a>(bundle: &'a Value, object_type: &str) -> Vec<&'a Value> {
+        bundle["objects"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|object| object["type"] == object_type)
+            .collect()
+    }
+
+    #[test]
+    fn test_to_bundle_validates_against_schema() {
+        let schema: Value = serde_json::from_str(
+            &std::fs::read_to_string("test_data/stix/bundle.schema.json").unwrap(),
+        )
+        .unwrap();
+        let schema = jsonschema::JSONSchema::compile(&schema).unwrap();
+
+        let bundle = to_bundle(&sample_records());
+        let errors: Vec<String> = match schema.validate(&bundle) {
+            Ok(_) => vec![],
+            Err(errors) => errors.map(|e| e.to_string()).collect(),
+        };
+        assert!(
+            errors.is_empty(),
+            "Bundle is not valid STIX 2.1: {:?}",
+            errors
+        );
+    }
+
+    #[test]
+    fn test_to_bundle_objects() {
+        let bundle = to_bundle(&sample_records());
+
+        let ipv4 = objects_of_type(&bundle, "ipv4-addr");
+        assert_eq!(1, ipv4.len());
+        assert_eq!(
+            "ipv4-addr--cbd67181-b9f8-595b-8bc3-3971e34fa1cc",
+            ipv4[0]["id"]
+        );
+        assert_eq!(1, objects_of_type(&bundle, "ipv6-addr").len());
+
+        let autonomous_systems = objects_of_type(&bundle, "autonomous-system");
+        assert_eq!(1, autonomous_systems.len());
+        assert_eq!(
+            "autonomous-system--0204c07d-e4dd-5f14-a3d5-c93cb1c5a9fc",
+            autonomous_systems[0]["id"]
+        );
+        assert_eq!(13335, autonomous_systems[0]["number"]);
+        assert_eq!("CLOUDFLARENET", autonomous_systems[0]["name"]);
+
+        let locations = objects_of_type(&bundle, "location");
+        assert_eq!(2, locations.len());
+        assert_eq!("AU", locations[0]["country"]);
+        assert_eq!("South Brisbane", locations[0]["city"]);
+        assert_eq!(-27.4766, locations[0]["latitude"]);
+        assert_eq!(
+            "South Brisbane, Queensland, Australia",
+            locations[0]["name"]
+        );
+
+        let relationships = objects_of_type(&bundle, "relationship");
+        assert_eq!(4, relationships.len());
+        assert!(relationships
+            .iter()
+            .any(|r| r["relationship_type"] == "belongs-to"
+                && r["source_ref"] == ipv4[0]["id"]
+                && r["target_ref"] == autonomous_systems[0]["id"]));
+        assert!(relationships
+            .iter()
+            .any(|r| r["relationship_type"] == "located-at"
+                && r["source_ref"] == ipv4[0]["id"]
+                && r["target_ref"] == locations[0]["id"]));
+    }
+}